@@ -63,11 +63,64 @@ pub fn perft2_bench(c: &mut Criterion) {
     });
 }
 
+// Full-width perft without bulk counting, so every leaf is made
+fn perft_copymake(pos: &Position, ci: &CastleInfo, depth: u32) -> u64 {
+    if depth == 0 {
+        return 1;
+    }
+    let mut count = 0;
+    for mv in pos.gen_pseudo_legals(ci) {
+        let mut new_pos = pos.clone();
+        if new_pos.make_move(mv, ci) {
+            count += perft_copymake(&new_pos, ci, depth - 1);
+        }
+    }
+    count
+}
+
+fn perft_makeunmake(pos: &mut Position, ci: &CastleInfo, depth: u32) -> u64 {
+    if depth == 0 {
+        return 1;
+    }
+    let mut count = 0;
+    for mv in pos.gen_pseudo_legals(ci) {
+        if let Some(undo) = pos.make_move_undo(mv, ci) {
+            count += perft_makeunmake(pos, ci, depth - 1);
+            pos.unmake_move(mv, ci, &undo);
+        }
+    }
+    count
+}
+
+pub fn copymake_bench(c: &mut Criterion) {
+    let bench_pos = load_bench();
+    c.bench_function("copymake", |b| {
+        b.iter(|| {
+            bench_pos
+                .iter()
+                .fold(0, |acc, (pos, ci)| acc + perft_copymake(pos, ci, 2))
+        })
+    });
+}
+
+pub fn makeunmake_bench(c: &mut Criterion) {
+    let mut bench_pos = load_bench();
+    c.bench_function("makeunmake", |b| {
+        b.iter(|| {
+            bench_pos
+                .iter_mut()
+                .fold(0, |acc, (pos, ci)| acc + perft_makeunmake(pos, ci, 2))
+        })
+    });
+}
+
 criterion_group!(
     benches,
     pseudolegal_bench,
     makemove_bench,
     perft1_bench,
     perft2_bench,
+    copymake_bench,
+    makeunmake_bench,
 );
 criterion_main!(benches);
@@ -0,0 +1,221 @@
+use crate::history::HashHist;
+use crate::pgn::{Game, PgnReader};
+use crate::r#move::*;
+use crate::search::{start_search, Limits};
+use crate::thread::{SharedState, Thread};
+use crate::types::*;
+
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Write};
+use std::sync::atomic::Ordering;
+use std::time::Instant;
+
+/*
+Every main line position is searched for its best move. If another move was played, the position
+is searched again with the root restricted to the played move, so that both scores come from a
+search of the same depth and the loss of the move is their difference.
+*/
+
+const DEFAULT_MOVETIME: u128 = 1000;
+
+// Losses in centipawns for the ? and ?? annotations
+const MISTAKE: i32 = 100;
+const BLUNDER: i32 = 300;
+const NAG_MISTAKE: u8 = 2;
+const NAG_BLUNDER: u8 = 4;
+// Length of the principal variation given for a better move
+const PV_PLIES: usize = 8;
+
+// Scores from white's view, in pawns or as a mate distance
+fn format_score(score: Score) -> String {
+    if score >= MATE_IN_MAX {
+        format!("#{}", (MATE - score + 1) / 2)
+    } else if score <= -MATE_IN_MAX {
+        format!("#-{}", (MATE + score + 1) / 2)
+    } else {
+        format!("{:+.2}", score as f64 / 100.0)
+    }
+}
+
+// The best move followed by the moves stored in the TT for the resulting positions
+fn principal_variation(thread: &mut Thread) -> Vec<Move> {
+    let (mut pos, ci) = (thread.root.clone(), thread.ci.clone());
+    let mut pv = Vec::new();
+    let mut mv = thread.best_move;
+    while pv.len() < PV_PLIES
+        && mv != NO_MOVE
+        && mv.is_pseudolegal(&pos, &ci)
+        && pos.make_move(mv, &ci)
+    {
+        pv.push(mv);
+        mv = thread.tt().read(&pos).map_or(NO_MOVE, |entry| entry.mv);
+    }
+    pv
+}
+
+// Searches the position of the node, restricted to the given moves unless there are none,
+// and returns the principal variation with its score
+fn search_node(
+    shared_state: &SharedState,
+    game: &Game,
+    node: usize,
+    hist: &HashHist,
+    template: &Limits,
+    root_moves: Vec<Move>,
+) -> (Vec<Move>, Score) {
+    shared_state.abort.store(false, Ordering::Relaxed);
+    shared_state.reset_nodes();
+    let limits = Limits {
+        start: Instant::now(),
+        ..template.clone()
+    };
+    let (pos, ci) = (game.nodes[node].pos.clone(), game.ci.clone());
+    let mut thread = Thread::new(shared_state, 0, pos, ci, hist.clone(), limits);
+    thread.root_moves = root_moves;
+    start_search(&mut thread);
+    (principal_variation(&mut thread), thread.best_score)
+}
+
+// Annotates the main line with evaluations, NAGs for bad moves and the lines of better moves
+// Puts the score in front of an existing comment
+fn add_eval(game: &mut Game, node: usize, score: Score) {
+    let eval = format_score(score);
+    let comment = &mut game.nodes[node].comment;
+    *comment = if comment.is_empty() {
+        eval
+    } else {
+        format!("{} {}", eval, comment)
+    };
+}
+
+// Follows a variation already in the game instead of adding the same move twice
+fn find_or_add_move(game: &mut Game, parent: usize, mv: Move) -> Option<usize> {
+    let children = &game.nodes[parent].children;
+    match children.iter().find(|&&child| game.nodes[child].mv == mv) {
+        Some(&child) => Some(child),
+        None => game.add_move(parent, mv),
+    }
+}
+
+fn annotate(shared_state: &SharedState, game: &mut Game, template: &Limits) {
+    let line = game.mainline();
+    let mut hist = HashHist::default();
+    for pair in line.windows(2) {
+        let (parent, node) = (pair[0], pair[1]);
+        if game.nodes[parent].pos.mr50 == 0 {
+            hist.clear();
+        }
+        hist.push(&game.nodes[parent].pos);
+
+        let played = game.nodes[node].mv;
+        let search =
+            |root_moves| search_node(shared_state, game, parent, &hist, template, root_moves);
+        let (pv, best_score) = search(Vec::new());
+        let best_move = pv.first().copied().unwrap_or(NO_MOVE);
+        // Nothing to compare against if the search was stopped without a move
+        if best_move == NO_MOVE {
+            continue;
+        }
+        let played_score = if played == best_move {
+            best_score
+        } else {
+            search(vec![played]).1
+        };
+        let ctm = game.nodes[parent].pos.ctm;
+        let white = |score: Score| if ctm == WHITE { score } else { -score };
+
+        add_eval(game, node, white(played_score));
+
+        let loss = best_score as i32 - played_score as i32;
+        if game.nodes[node].mv == best_move || loss < MISTAKE {
+            continue;
+        }
+        let nag = if loss >= BLUNDER {
+            NAG_BLUNDER
+        } else {
+            NAG_MISTAKE
+        };
+        game.nodes[node].nags.push(nag);
+        if let Some(variation) = find_or_add_move(game, parent, best_move) {
+            add_eval(game, variation, white(best_score));
+            let mut node = variation;
+            for &mv in pv[1..].iter() {
+                node = match find_or_add_move(game, node, mv) {
+                    Some(child) => child,
+                    None => break,
+                };
+            }
+        }
+    }
+}
+
+// Annotates the games of a PGN file:
+// analyze <input> <output> [depth <n>] [movetime <ms>] [nodes <n>]
+pub fn analyze(args: &[String]) {
+    let usage = "Usage: analyze <input> <output> [depth <n>] [movetime <ms>] [nodes <n>]";
+    let (input, output) = match args {
+        [input, output, ..] => (input, output),
+        _ => return println!("{}", usage),
+    };
+
+    let mut template = Limits {
+        is_silent: true,
+        ..Default::default()
+    };
+    let mut tokens = args[2..].iter();
+    while let Some(token) = tokens.next() {
+        let value = tokens.next().map(|x| x.as_str()).unwrap_or("");
+        match token.as_str() {
+            "depth" => template.depth = value.parse().unwrap_or(template.depth),
+            "movetime" => template.movetime = value.parse().unwrap_or(DEFAULT_MOVETIME),
+            "nodes" => template.nodes = value.parse().unwrap_or(0),
+            _ => return println!("{}", usage),
+        }
+    }
+    if !args[2..]
+        .iter()
+        .any(|x| ["depth", "movetime", "nodes"].contains(&x.as_str()))
+    {
+        template.movetime = DEFAULT_MOVETIME;
+    }
+    template.spend = template.movetime;
+    template.is_time_limit = template.movetime != 0;
+
+    let reader = match File::open(input) {
+        Ok(file) => PgnReader::new(BufReader::new(file)),
+        Err(err) => return println!("Failed to read {}: {}", input, err),
+    };
+    let mut writer = match File::create(output) {
+        Ok(file) => BufWriter::new(file),
+        Err(err) => return println!("Failed to create {}: {}", output, err),
+    };
+
+    let mut shared_state = SharedState::default();
+    shared_state.launch_threads(1);
+
+    let start = Instant::now();
+    for (i, game) in reader.enumerate() {
+        let mut game = match game {
+            Ok(game) => game,
+            Err(err) => {
+                println!("Game {}: {}", i + 1, err);
+                continue;
+            }
+        };
+        annotate(&shared_state, &mut game, &template);
+        if let Err(err) = writer.write_all(game.to_pgn().as_bytes()) {
+            return println!("Failed to write {}: {}", output, err);
+        }
+        let count = |nag| game.nodes.iter().filter(|x| x.nags.contains(&nag)).count();
+        println!(
+            "Game {}: {} mistakes, {} blunders - Time {:.3}",
+            i + 1,
+            count(NAG_MISTAKE),
+            count(NAG_BLUNDER),
+            start.elapsed().as_secs_f64()
+        );
+    }
+    if let Err(err) = writer.flush() {
+        println!("Failed to write {}: {}", output, err);
+    }
+}
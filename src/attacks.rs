@@ -11,6 +11,41 @@ pub const fn between_inc_bb(sq1: Square, sq2: Square) -> BitBoard {
     BETWEEN_BB[sq1 as usize][sq2 as usize].or(bb!(sq1, sq2))
 }
 
+const fn ray_bb(sq: Square, dir: Direction) -> BitBoard {
+    let mut res = BB_ZERO;
+    let mut ray = bb!(sq).shift(dir);
+    while ray.0 != 0 {
+        res = res.or(ray);
+        ray = ray.shift(dir);
+    }
+    res
+}
+
+// The full line through two aligned squares, empty if they are not aligned
+#[allow(clippy::large_const_arrays)]
+pub const LINE_BB: [[BitBoard; 64]; 64] = {
+    let dirs = [NORTH, NORTH_EAST, EAST, SOUTH_EAST];
+    let mut res = [[BB_ZERO; 64]; 64];
+
+    let mut sq = A1;
+    while sq <= H8 {
+        let mut i = 0;
+        while i < dirs.len() {
+            let line = ray_bb(sq, dirs[i]).or(ray_bb(sq, -dirs[i])).or(bb!(sq));
+            let mut rest = line.xor(bb!(sq));
+            while rest.0 != 0 {
+                let to = rest.0.trailing_zeros();
+                res[sq as usize][to as usize] = line;
+                rest = rest.xor(bb!(to));
+            }
+            i += 1;
+        }
+        sq += 1;
+    }
+
+    res
+};
+
 pub const fn pawn_attack_bb(sq: Square, c: Color) -> BitBoard {
     pawn_bb_attack_bb(bb!(sq), c)
 }
@@ -6,7 +6,7 @@ use crate::thread::{SharedState, Thread};
 
 use std::time::Instant;
 
-const BENCHMARKING_POSITIONS: [&str; 50] = [
+pub(crate) const BENCHMARKING_POSITIONS: [&str; 50] = [
     "r3qbrk/6p1/2b2pPp/p3pP1Q/PpPpP2P/3P1B2/2PB3K/R5R1 w - - 16 42",
     "6k1/1R3p2/6p1/2Bp3p/3P2q1/P7/1P2rQ1K/5R2 b - - 4 44",
     "8/8/1p2k1p1/3p3p/1p1P1P1P/1P2PK2/8/8 w - - 3 54",
@@ -62,7 +62,7 @@ const BENCHMARKING_POSITIONS: [&str; 50] = [
 pub fn load_bench() -> Vec<(Position, CastleInfo)> {
     BENCHMARKING_POSITIONS
         .iter()
-        .map(|&x| Position::parse_fen(x))
+        .map(|&x| Position::from_fen(x).unwrap())
         .collect::<Vec<(Position, CastleInfo)>>()
 }
 
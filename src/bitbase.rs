@@ -0,0 +1,81 @@
+use crate::endgame::KNOWN_WIN;
+use crate::position::Position;
+use crate::types::*;
+
+include!(concat!(env!("OUT_DIR"), "/codegen_kpk.rs"));
+
+// Has to match the indexing used by the generator in build.rs
+fn kpk_index(stm: Color, bksq: Square, wksq: Square, psq: Square) -> usize {
+    let pawn = file_of(psq) + FILE_NB / 2 * (rank_of(psq) - RANK_2);
+    stm as usize | (bksq as usize) << 1 | (wksq as usize) << 7 | pawn << 13
+}
+
+// Squares are given from the point of view of the side with the pawn
+pub fn kpk_is_win(strong_k: Square, pawn: Square, weak_k: Square, strong_to_move: bool) -> bool {
+    // Mirror the pawn onto files A-D
+    let flip = if file_of(pawn) > FILE_D { 7 } else { 0 };
+    let stm = if strong_to_move { WHITE } else { BLACK };
+    let idx = kpk_index(stm, weak_k ^ flip, strong_k ^ flip, pawn ^ flip);
+    KPK[idx / 64] & (1 << (idx % 64)) != 0
+}
+
+// Exact KPK result relative to the side to move, None if the position is not KPK
+pub fn probe_kpk(pos: &Position) -> Option<Score> {
+    let pawns = pos.piecetype_bb(PAWN);
+    if pos.piecetype_bb(ALL).popcount() != 3 || pawns.popcount() != 1 {
+        return None;
+    }
+
+    let pawn = pawns.lsb();
+    let strong = color_of(pos.piece_on(pawn).unwrap());
+    // Flip the board vertically if the pawn is black
+    let flip = if strong == WHITE { 0 } else { 56 };
+    let (strong_k, weak_k) = (pos.king_sq(strong), pos.king_sq(swap_color(strong)));
+    if !kpk_is_win(
+        strong_k ^ flip,
+        pawn ^ flip,
+        weak_k ^ flip,
+        pos.ctm == strong,
+    ) {
+        return Some(0);
+    }
+
+    let score = KNOWN_WIN + 20 * relative_rank(rank_of(pawn), strong) as Score;
+    Some(if pos.ctm == strong { score } else { -score })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn probe(fen: &str) -> Score {
+        probe_kpk(&Position::from_fen(fen).unwrap().0).unwrap()
+    }
+
+    #[test]
+    fn kpk_results() {
+        // Rook pawns are drawn once the defending king reaches the corner
+        assert_eq!(probe("k7/8/1K6/P7/8/8/8/8 w - - 0 1"), 0);
+        assert_eq!(probe("8/8/8/8/8/1k6/7p/7K b - - 0 1"), 0);
+        // The king on the sixth in front of its pawn wins, whoever moves
+        assert!(probe("4k3/8/4K3/4P3/8/8/8/8 w - - 0 1") > 0);
+        assert!(probe("4k3/8/4K3/4P3/8/8/8/8 b - - 0 1") < 0);
+        // Direct opposition: whoever has to move gives way
+        assert!(probe("8/8/4k3/8/4K3/4P3/8/8 b - - 0 1") < 0);
+        assert_eq!(probe("8/8/4k3/8/4K3/4P3/8/8 w - - 0 1"), 0);
+        // The defending king in front of the pawn holds the draw
+        assert_eq!(probe("8/8/8/4k3/4P3/4K3/8/8 w - - 0 1"), 0);
+        // Stalemate with the pawn on the seventh, unless white can still give way with the king
+        assert_eq!(probe("4k3/4P3/4K3/8/8/8/8/8 b - - 0 1"), 0);
+        assert!(probe("4k3/4P3/4K3/8/8/8/8/8 w - - 0 1") > 0);
+        // Black pawns are flipped
+        assert!(probe("8/8/8/8/4p3/4k3/8/4K3 b - - 0 1") > 0);
+    }
+
+    #[test]
+    fn kpk_win_count() {
+        // Number of wins in Stockfish's KPK bitbase with the same indexing
+        let wins = KPK.iter().map(|x| x.count_ones()).sum::<u32>();
+        assert_eq!(wins, 111282);
+    }
+}
@@ -26,7 +26,7 @@ pub fn main() {
     let magic_path = Path::new(&out_dir).join("codegen_attacks.rs");
     let mut file = File::create(magic_path).unwrap();
 
-    let has_bmi2 = env::var("CARGO_CFG_TARGET_FEATURE").map_or(false, |x| x.contains("bmi2"));
+    let has_bmi2 = env::var("CARGO_CFG_TARGET_FEATURE").is_ok_and(|x| x.contains("bmi2"));
     if has_bmi2 {
         writeln!(file, "//Tables for BMI2").unwrap();
     } else {
@@ -43,10 +43,21 @@ pub fn main() {
     let between_bb = init_between_bb();
     write!(
         file,
-        "#[rustfmt::skip]\n pub const BETWEEN_BB: [[BitBoard; 64]; 64] = {};\n",
+        "#[rustfmt::skip]\n#[allow(clippy::large_const_arrays)]\n pub const BETWEEN_BB: [[BitBoard; 64]; 64] = {};\n",
         print_arr2d(&between_bb, true)
     )
     .unwrap();
+
+    let kpk_path = Path::new(&out_dir).join("codegen_kpk.rs");
+    let mut file = File::create(kpk_path).unwrap();
+    let kpk = init_kpk();
+    write!(
+        file,
+        "#[rustfmt::skip]\n pub static KPK: [u64; {}] = {};\n",
+        kpk.len(),
+        print_arr1d(&kpk, false)
+    )
+    .unwrap();
 }
 
 pub fn print_arr2d(arr: &[Vec<BitBoard>], bb: bool) -> String {
@@ -128,3 +139,120 @@ pub fn init_between_bb() -> Vec<Vec<BitBoard>> {
     }
     res
 }
+
+// KPK retrograde analysis, always from the point of view of white being the side with the pawn.
+// Has to match the indexing in bitbase.rs: stm | black king << 1 | white king << 7 | pawn << 13,
+// where the pawn is restricted to files A-D and ranks 2-7.
+const KPK_SIZE: usize = 2 * 64 * 64 * 24;
+
+const INVALID: u8 = 0;
+const UNKNOWN: u8 = 1;
+const DRAW: u8 = 2;
+const WIN: u8 = 4;
+
+fn kpk_index(stm: Color, bksq: Square, wksq: Square, psq: Square) -> usize {
+    let pawn = file_of(psq) + FILE_NB / 2 * (rank_of(psq) - RANK_2);
+    stm as usize | (bksq as usize) << 1 | (wksq as usize) << 7 | pawn << 13
+}
+
+fn king_attacks(sq: Square) -> BitBoard {
+    KING_DIRS
+        .iter()
+        .fold(BB_ZERO, |acc, &dir| acc | bb!(sq).shift(dir))
+}
+
+fn white_pawn_attacks(sq: Square) -> BitBoard {
+    bb!(sq).shift(NORTH_WEST) | bb!(sq).shift(NORTH_EAST)
+}
+
+fn kpk_init_entry(stm: Color, bksq: Square, wksq: Square, psq: Square) -> u8 {
+    let push_sq = psq + NORTH as Square;
+    if distance(wksq, bksq) <= 1
+        || wksq == psq
+        || bksq == psq
+        || stm == WHITE && (white_pawn_attacks(psq) & bb!(bksq)).not_empty()
+    {
+        INVALID
+    } else if stm == WHITE
+        && rank_of(psq) == RANK_7
+        && wksq != push_sq
+        && (distance(bksq, push_sq) > 1 || distance(wksq, push_sq) == 1)
+    {
+        WIN
+    } else if stm == BLACK
+        && ((king_attacks(bksq) & !(king_attacks(wksq) | white_pawn_attacks(psq))).is_empty()
+            || (king_attacks(bksq) & bb!(psq) & !king_attacks(wksq)).not_empty())
+    {
+        DRAW
+    } else {
+        UNKNOWN
+    }
+}
+
+fn kpk_classify(db: &[u8], stm: Color, bksq: Square, wksq: Square, psq: Square) -> u8 {
+    let (good, bad) = if stm == WHITE {
+        (WIN, DRAW)
+    } else {
+        (DRAW, WIN)
+    };
+    let mut r = INVALID;
+    if stm == WHITE {
+        for to in king_attacks(wksq) {
+            r |= db[kpk_index(BLACK, bksq, to, psq)];
+        }
+        if rank_of(psq) < RANK_7 {
+            let push_sq = psq + NORTH as Square;
+            r |= db[kpk_index(BLACK, bksq, wksq, push_sq)];
+            if rank_of(psq) == RANK_2 && push_sq != wksq && push_sq != bksq {
+                r |= db[kpk_index(BLACK, bksq, wksq, push_sq + NORTH as Square)];
+            }
+        }
+    } else {
+        for to in king_attacks(bksq) {
+            r |= db[kpk_index(WHITE, to, wksq, psq)];
+        }
+    }
+
+    if r & good != 0 {
+        good
+    } else if r & UNKNOWN != 0 {
+        UNKNOWN
+    } else {
+        bad
+    }
+}
+
+pub fn init_kpk() -> Vec<BitBoard> {
+    let mut db = vec![INVALID; KPK_SIZE];
+    let mut entries = Vec::with_capacity(KPK_SIZE);
+    for &stm in [WHITE, BLACK].iter() {
+        for bksq in A1..=H8 {
+            for wksq in A1..=H8 {
+                for psq in (A2..=H7).filter(|&sq| file_of(sq) <= FILE_D) {
+                    let idx = kpk_index(stm, bksq, wksq, psq);
+                    db[idx] = kpk_init_entry(stm, bksq, wksq, psq);
+                    entries.push((idx, stm, bksq, wksq, psq));
+                }
+            }
+        }
+    }
+
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for &(idx, stm, bksq, wksq, psq) in entries.iter() {
+            if db[idx] == UNKNOWN {
+                db[idx] = kpk_classify(&db, stm, bksq, wksq, psq);
+                changed |= db[idx] != UNKNOWN;
+            }
+        }
+    }
+
+    let mut res = vec![BB_ZERO; KPK_SIZE / 64];
+    for (idx, &result) in db.iter().enumerate() {
+        if result == WIN {
+            res[idx / 64] |= bb!(idx % 64);
+        }
+    }
+    res
+}
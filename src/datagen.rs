@@ -0,0 +1,379 @@
+use crate::history::HashHist;
+use crate::position::{CastleInfo, Position};
+use crate::r#move::*;
+use crate::rng::{time_seed, Rng};
+use crate::search::{start_search, Limits};
+use crate::thread::{SharedState, Thread};
+use crate::transposition::DEFAULT_TT_SIZE;
+use crate::types::*;
+
+use std::convert::TryInto;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+/*
+Positions are written as 32 byte little-endian records:
+  occupancy: u64 | pieces: [u8; 16] | score: i16 | ctm: u8 | ep: u8 | mr50: u8 | result: u8 |
+  fullmove: u16
+The pieces are packed two per byte in occupancy order, low nibble first, using Scam's piece codes.
+Rooks which can still castle are stored as piece code 7 (white) and 15 (black). The score is
+from white's view, the result is 0 for a black win, 1 for a draw and 2 for a white win.
+*/
+
+const RECORD_SIZE: usize = 32;
+const CASTLING_ROOK: Piece = 7;
+
+const DEFAULT_GAMES: usize = 100;
+const DEFAULT_NODES: u64 = 5000;
+const DEFAULT_RANDOM_PLIES: usize = 8;
+const MAX_PLIES: usize = 400;
+
+// Adjudicate a win after this many plies with a score beyond the margin for the same side,
+// and a draw after this many plies of scores within the margin past the minimum ply
+const WIN_PLIES: usize = 4;
+const WIN_SCORE: Score = 1500;
+const DRAW_PLIES: usize = 8;
+const DRAW_SCORE: Score = 10;
+const DRAW_MIN_PLY: usize = 80;
+
+struct Record {
+    pos: Position,
+    ci: CastleInfo,
+    score: Score,
+}
+
+// Plays random legal plies from the start position, retrying if the game ends on the way
+fn random_opening(rng: &mut Rng, frc: bool, plies: usize) -> (Position, CastleInfo) {
+    loop {
+        let (mut pos, ci) = if frc {
+            Position::frc_startpos(rng.below(960) as u16)
+        } else {
+            Position::startpos()
+        };
+        for _ in 0..plies {
+            let moves = pos.gen_legals(&ci).collect::<Vec<Move>>();
+            if moves.is_empty() {
+                break;
+            }
+            pos.make_move(moves[rng.below(moves.len())], &ci);
+        }
+        if !pos.gen_legals(&ci).is_empty() {
+            return (pos, ci);
+        }
+    }
+}
+
+// Plays a self-play game and returns its quiet positions and the result from white's view
+fn play_game(
+    shared_state: &mut SharedState,
+    rng: &mut Rng,
+    nodes: u64,
+    random_plies: usize,
+    frc: bool,
+) -> (Vec<Record>, u8) {
+    let (mut pos, ci) = random_opening(rng, frc, random_plies);
+    shared_state.reallocate_tt(DEFAULT_TT_SIZE);
+    let mut hist = HashHist::default();
+    hist.push(&pos);
+    let mut hashes = vec![pos.hash];
+
+    let mut records = Vec::new();
+    // Plies in a row with a winning score, negative for black
+    let (mut win_plies, mut draw_plies) = (0i32, 0);
+    let mut ply = 0;
+    let result = loop {
+        let moves = pos.gen_legals(&ci).collect::<Vec<Move>>();
+        if moves.is_empty() {
+            break if !pos.in_check(pos.ctm) {
+                1
+            } else if pos.ctm == WHITE {
+                0
+            } else {
+                2
+            };
+        }
+        let repetitions = hashes.iter().filter(|&&x| x == pos.hash).count();
+        if pos.mr50 >= 100 || repetitions >= 3 || pos.is_insufficient_material() || ply >= MAX_PLIES
+        {
+            break 1;
+        }
+
+        shared_state.abort.store(false, Ordering::Relaxed);
+        shared_state.reset_nodes();
+        let limits = Limits {
+            nodes,
+            is_silent: true,
+            ..Default::default()
+        };
+        let mut thread = Thread::new(
+            shared_state,
+            0,
+            pos.clone(),
+            ci.clone(),
+            hist.clone(),
+            limits,
+        );
+        start_search(&mut thread);
+        let (mv, score) = (thread.best_move, thread.best_score);
+        let mv = if moves.contains(&mv) { mv } else { moves[0] };
+        let white_score = if pos.ctm == WHITE { score } else { -score };
+
+        if !pos.in_check(pos.ctm) && pos.is_quiet(mv) && score.abs() < MATE_IN_MAX {
+            records.push(Record {
+                pos: pos.clone(),
+                ci: ci.clone(),
+                score: white_score,
+            });
+        }
+
+        win_plies = match white_score {
+            s if s >= WIN_SCORE => win_plies.max(0) + 1,
+            s if s <= -WIN_SCORE => win_plies.min(0) - 1,
+            _ => 0,
+        };
+        draw_plies = if score.abs() <= DRAW_SCORE {
+            draw_plies + 1
+        } else {
+            0
+        };
+        if win_plies.unsigned_abs() as usize >= WIN_PLIES {
+            break if win_plies > 0 { 2 } else { 0 };
+        }
+        if ply >= DRAW_MIN_PLY && draw_plies >= DRAW_PLIES {
+            break 1;
+        }
+
+        pos.make_move(mv, &ci);
+        if pos.mr50 == 0 {
+            hist.clear();
+            hashes.clear();
+        }
+        hist.push(&pos);
+        hashes.push(pos.hash);
+        ply += 1;
+    };
+    (records, result)
+}
+
+fn encode(record: &Record, result: u8) -> [u8; RECORD_SIZE] {
+    let pos = &record.pos;
+    let mut bytes = [0; RECORD_SIZE];
+    let occ = pos.piecetype_bb(ALL);
+    bytes[..8].copy_from_slice(&occ.0.to_le_bytes());
+    for (i, sq) in occ.enumerate() {
+        let mut piece = pos.piece_on(sq).unwrap();
+        let castling_rook = [W_KS, W_QS, B_KS, B_QS]
+            .iter()
+            .any(|&cr| pos.cr & cr != 0 && record.ci.castle_rooks[cr as usize] == sq);
+        if castling_rook {
+            piece = make_piece(color_of(piece), CASTLING_ROOK);
+        }
+        bytes[8 + i / 2] |= piece << (4 * (i % 2));
+    }
+    bytes[24..26].copy_from_slice(&record.score.to_le_bytes());
+    bytes[26] = pos.ctm;
+    bytes[27] = pos.ep as u8;
+    bytes[28] = pos.mr50.min(u8::MAX as u16) as u8;
+    bytes[29] = result;
+    bytes[30..32].copy_from_slice(&pos.fullmove.to_le_bytes());
+    bytes
+}
+
+// The record as a FEN, castling uses K/Q for the outermost rooks and file letters otherwise
+fn decode(bytes: &[u8]) -> (String, Score, u8) {
+    let occ = u64::from_le_bytes(bytes[..8].try_into().unwrap());
+    let mut board = [0; 64];
+    let mut castling_rooks = Vec::new();
+    for (i, sq) in (0..64).filter(|&sq| occ & (1u64 << sq) != 0).enumerate() {
+        let mut piece = ((bytes[8 + i / 2] >> (4 * (i % 2))) & 0xF) as Piece;
+        if piecetype_of(piece) == CASTLING_ROOK {
+            piece = make_piece(color_of(piece), ROOK);
+            castling_rooks.push((color_of(piece), sq));
+        }
+        board[sq as usize] = piece;
+    }
+
+    let mut fen = String::new();
+    for rank in (RANK_1..RANK_NB).rev() {
+        let mut empty = 0;
+        for file in FILE_A..FILE_NB {
+            let piece = board[to_square(rank, file) as usize];
+            if piece == 0 {
+                empty += 1;
+                continue;
+            }
+            if empty > 0 {
+                fen.push_str(&empty.to_string());
+                empty = 0;
+            }
+            fen.push(piece_to_char(piece));
+        }
+        if empty > 0 {
+            fen.push_str(&empty.to_string());
+        }
+        if rank != RANK_1 {
+            fen.push('/');
+        }
+    }
+
+    let mut castling = String::new();
+    for &color in [WHITE, BLACK].iter() {
+        let back_rank = (0..8).map(|file| to_square(relative_rank(RANK_1, color), file));
+        let king = back_rank
+            .clone()
+            .find(|&sq| board[sq as usize] == make_piece(color, KING));
+        let rooks = back_rank
+            .filter(|&sq| board[sq as usize] == make_piece(color, ROOK))
+            .collect::<Vec<Square>>();
+        // Kingside rooks first
+        for &(_, sq) in castling_rooks.iter().filter(|x| x.0 == color).rev() {
+            let kingside = king.is_some_and(|k| sq > k);
+            let c = if kingside && rooks.last() == Some(&sq) {
+                'K'
+            } else if !kingside && rooks.first() == Some(&sq) {
+                'Q'
+            } else {
+                file_to_char(file_of(sq)).to_ascii_uppercase()
+            };
+            castling.push(if color == WHITE {
+                c
+            } else {
+                c.to_ascii_lowercase()
+            });
+        }
+    }
+    if castling.is_empty() {
+        castling.push('-');
+    }
+
+    let ep = match bytes[27] as Square {
+        A1 => "-".to_owned(),
+        sq => square_to_str(sq),
+    };
+    let fullmove = u16::from_le_bytes([bytes[30], bytes[31]]);
+    let fen = format!(
+        "{} {} {} {} {} {}",
+        fen,
+        if bytes[26] == WHITE { 'w' } else { 'b' },
+        castling,
+        ep,
+        bytes[28],
+        fullmove
+    );
+    (fen, i16::from_le_bytes([bytes[24], bytes[25]]), bytes[29])
+}
+
+// Converts a binary file to lines of: <fen> | <white score> | <white result>
+fn convert(args: &[String]) {
+    let (input, output) = match args {
+        [input, output] => (input, output),
+        _ => return println!("Usage: datagen convert <input> <output>"),
+    };
+    let run = || -> io::Result<usize> {
+        let mut reader = BufReader::new(File::open(input)?);
+        let mut writer = BufWriter::new(File::create(output)?);
+        let mut bytes = [0; RECORD_SIZE];
+        let mut count = 0;
+        loop {
+            match reader.read_exact(&mut bytes) {
+                Ok(()) => {}
+                Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => break,
+                Err(err) => return Err(err),
+            }
+            let (fen, score, result) = decode(&bytes);
+            let result = ["0.0", "0.5", "1.0"][result as usize];
+            writeln!(writer, "{} | {} | {}", fen, score, result)?;
+            count += 1;
+        }
+        writer.flush()?;
+        Ok(count)
+    };
+    match run() {
+        Ok(count) => println!("Converted {} positions", count),
+        Err(err) => println!("Failed to convert {}: {}", input, err),
+    }
+}
+
+// Generates self-play data:
+// datagen <file> [games <n>] [threads <n>] [nodes <n>] [random <plies>] [frc]
+// datagen convert <input> <output>
+pub fn datagen(args: &[String]) {
+    let usage =
+        "Usage: datagen <file> [games <n>] [threads <n>] [nodes <n>] [random <plies>] [frc]";
+    match args.first().map(|x| x.as_str()) {
+        Some("convert") => return convert(&args[1..]),
+        Some(_) => {}
+        None => return println!("{}", usage),
+    }
+
+    let (mut games, mut threads, mut nodes) = (DEFAULT_GAMES, 1, DEFAULT_NODES);
+    let (mut random_plies, mut frc) = (DEFAULT_RANDOM_PLIES, false);
+    let mut tokens = args[1..].iter();
+    while let Some(token) = tokens.next() {
+        if token == "frc" {
+            frc = true;
+            continue;
+        }
+        let value = tokens.next().and_then(|x| x.parse::<u64>().ok());
+        match (token.as_str(), value) {
+            ("games", Some(v)) => games = v as usize,
+            ("threads", Some(v)) => threads = (v as usize).max(1),
+            ("nodes", Some(v)) => nodes = v,
+            ("random", Some(v)) => random_plies = v as usize,
+            _ => return println!("{}", usage),
+        }
+    }
+
+    let writer = match File::create(&args[0]) {
+        Ok(file) => Arc::new(Mutex::new(BufWriter::new(file))),
+        Err(err) => return println!("Failed to create {}: {}", args[0], err),
+    };
+    let (started, finished) = (Arc::new(AtomicUsize::new(0)), Arc::new(AtomicUsize::new(0)));
+    let positions = Arc::new(AtomicUsize::new(0));
+    let seed = time_seed();
+    let start = Instant::now();
+
+    let handles = (0..threads)
+        .map(|id| {
+            let (writer, positions) = (writer.clone(), positions.clone());
+            let (started, finished) = (started.clone(), finished.clone());
+            std::thread::spawn(move || {
+                let mut rng = Rng::new(seed ^ (id as u64).wrapping_mul(0x9E3779B97F4A7C15));
+                let mut shared_state = SharedState::default();
+                shared_state.launch_threads(1);
+                while started.fetch_add(1, Ordering::Relaxed) < games {
+                    let (records, result) =
+                        play_game(&mut shared_state, &mut rng, nodes, random_plies, frc);
+                    let mut writer = writer.lock().unwrap();
+                    for record in records.iter() {
+                        writer.write_all(&encode(record, result)).unwrap();
+                    }
+                    let total = positions.fetch_add(records.len(), Ordering::Relaxed);
+                    let done = finished.fetch_add(1, Ordering::Relaxed) + 1;
+                    if done % 10 == 0 {
+                        println!(
+                            "{} games, {} positions - Time {:.3}",
+                            done,
+                            total + records.len(),
+                            start.elapsed().as_secs_f64()
+                        );
+                    }
+                }
+            })
+        })
+        .collect::<Vec<_>>();
+    for handle in handles {
+        handle.join().unwrap();
+    }
+    writer.lock().unwrap().flush().unwrap();
+
+    println!(
+        "\n{} games, {} positions - Time {:.3}",
+        games,
+        positions.load(Ordering::Relaxed),
+        start.elapsed().as_secs_f64()
+    );
+}
@@ -0,0 +1,493 @@
+use crate::attacks::*;
+use crate::bitboard::*;
+use crate::endgame::{signature, MaterialKey};
+use crate::position::{CastleInfo, Position};
+use crate::types::*;
+
+use std::collections::HashMap;
+use std::path::Path;
+use std::time::Instant;
+
+/*
+Distance to mate tables for small endgames, generated by retrograde analysis with `scam gentb`.
+A table file <CODE>.dtm (e.g. KQKR.dtm) consists of:
+  magic "SDTM" (4 byte) | piece count n (1 byte) | n pieces (1 byte each) | 2 * 64^n values
+The pieces are stored in index order, the side named first in the code is always white. The value
+of a position is found at index stm + 2 * (sq_0 + 64 * sq_1 + ... + 64^(n-1) * sq_(n-1)), where
+sq_i is the square of the i-th piece. A value of 0 is a draw (or an illegal position), 1..=127 is
+a mate in that many moves for the side to move and 128 + n means that the side to move gets mated
+in n moves. Castling rights, en passant and the 50 move rule are ignored.
+*/
+
+const MAGIC: [u8; 4] = *b"SDTM";
+const MAX_PIECES: usize = 4;
+const MAX_PLIES: usize = 255;
+
+const DRAW: u8 = 0;
+const LOSS: u8 = 128;
+
+// Supported tables in generation order, each with the tables reachable by captures and promotions
+pub const DTM_TABLES: [(&str, &[&str]); 5] = [
+    ("KQK", &[]),
+    ("KRK", &[]),
+    ("KBNK", &[]),
+    ("KPK", &["KQK", "KRK"]),
+    ("KQKR", &["KQK", "KRK"]),
+];
+
+const fn plies_to_value(plies: usize) -> u8 {
+    if plies % 2 == 1 {
+        (plies as u8).div_ceil(2)
+    } else {
+        LOSS + plies as u8 / 2
+    }
+}
+
+pub struct DtmTable {
+    pieces: Vec<Piece>,
+    key: MaterialKey,
+    data: Vec<u8>,
+}
+
+impl DtmTable {
+    fn new(code: &str) -> Option<DtmTable> {
+        let mut pieces = Vec::new();
+        let mut color = BLACK;
+        let mut kings = 0;
+        for c in code.chars() {
+            let pt = ".PNBRQK".find(c)? as PieceType;
+            if pt == ALL {
+                return None;
+            }
+            if pt == KING {
+                color = swap_color(color);
+                kings += 1;
+            }
+            pieces.push(make_piece(color, pt));
+        }
+        if !(3..=MAX_PIECES).contains(&pieces.len()) || kings != 2 || pieces[0] != W_KING {
+            return None;
+        }
+        // Kings first, as they restrict the placement of all other pieces the most
+        pieces.sort_by_key(|&p| (piecetype_of(p) != KING, p));
+
+        let key = signature(code, WHITE);
+        Some(DtmTable {
+            pieces,
+            key,
+            data: Vec::new(),
+        })
+    }
+
+    pub fn code(&self) -> String {
+        pieces_code(&self.pieces)
+    }
+
+    fn size(&self) -> usize {
+        2 << (6 * self.pieces.len())
+    }
+
+    fn index(&self, squares: &[Square], stm: Color) -> usize {
+        let sqs = squares
+            .iter()
+            .rev()
+            .fold(0, |acc, &sq| acc * 64 + sq as usize);
+        stm as usize + 2 * sqs
+    }
+
+    // Returns the squares of the pieces and the side to move, or None for illegal placements
+    fn decode(&self, idx: usize) -> Option<([Square; MAX_PIECES], Color)> {
+        let mut squares = [A1; MAX_PIECES];
+        let mut occ = BB_ZERO;
+        for (i, &piece) in self.pieces.iter().enumerate() {
+            let sq = (idx >> (1 + 6 * i) & 63) as Square;
+            let back_rank = rank_of(sq) == RANK_1 || rank_of(sq) == RANK_8;
+            if (occ & bb!(sq)).not_empty() || piecetype_of(piece) == PAWN && back_rank {
+                return None;
+            }
+            occ |= bb!(sq);
+            squares[i] = sq;
+        }
+        Some((squares, (idx & 1) as Color))
+    }
+
+    fn position(&self, squares: &[Square], stm: Color) -> Position {
+        let mut pieces = [(0, A1); MAX_PIECES];
+        for (i, &piece) in self.pieces.iter().enumerate() {
+            pieces[i] = (piece, squares[i]);
+        }
+        Position::from_pieces(&pieces[..self.pieces.len()], stm)
+    }
+
+    fn probe(&self, pos: &Position) -> u8 {
+        // Tables are stored with the strong side as white, so the board may have to be flipped
+        let flip = pos.material_key() != self.key;
+        let (color_flip, sq_flip) = if flip { (1, 56) } else { (0, 0) };
+
+        let mut squares = [A1; MAX_PIECES];
+        let mut used = BB_ZERO;
+        for (i, &piece) in self.pieces.iter().enumerate() {
+            let (c, pt) = (color_of(piece) ^ color_flip, piecetype_of(piece));
+            let sq = (pos.piece_bb(pt, c) & !used).lsb();
+            used |= bb!(sq);
+            squares[i] = sq ^ sq_flip;
+        }
+        self.data[self.index(&squares[..self.pieces.len()], pos.ctm ^ color_flip)]
+    }
+
+    // Calls f with the index of every legal position from which the side that just moved could
+    // have reached the given one without changing material
+    fn predecessors(&self, squares: &[Square], stm: Color, mut f: impl FnMut(usize)) {
+        let n = self.pieces.len();
+        let mover = swap_color(stm);
+        let occ = squares[..n].iter().fold(BB_ZERO, |acc, &sq| acc | bb!(sq));
+
+        for (i, &piece) in self.pieces.iter().enumerate() {
+            if color_of(piece) != mover {
+                continue;
+            }
+            let sq = squares[i];
+            let origins = if piecetype_of(piece) == PAWN {
+                let single = (sq as Direction - relative_dir(NORTH, mover)) as Square;
+                let double = (single as Direction - relative_dir(NORTH, mover)) as Square;
+                let mut origins = BB_ZERO;
+                let rank = relative_rank(rank_of(sq), mover);
+                if rank >= RANK_3 && (occ & bb!(single)).is_empty() {
+                    origins |= bb!(single);
+                    if rank == RANK_4 && (occ & bb!(double)).is_empty() {
+                        origins |= bb!(double);
+                    }
+                }
+                origins
+            } else {
+                attack_bb(piecetype_of(piece), sq, occ) & !occ
+            };
+
+            for from in origins {
+                let mut prev = [A1; MAX_PIECES];
+                prev[..n].copy_from_slice(&squares[..n]);
+                prev[i] = from;
+                if !self.position(&prev[..n], mover).in_check(stm) {
+                    f(self.index(&prev[..n], mover));
+                }
+            }
+        }
+    }
+
+    pub fn generate(code: &str, tables: &DtmTables) -> Result<DtmTable, String> {
+        let mut table = DtmTable::new(code).ok_or(format!("Invalid table {}", code))?;
+        let size = table.size();
+        let ci = CastleInfo::default();
+
+        let mut values = vec![DRAW; size];
+        let mut remaining = vec![0u8; size];
+        // Positions which get decided at a given ply and losing exits which are resolved then
+        let mut pending: Vec<Vec<u32>> = vec![Vec::new(); MAX_PLIES + 1];
+        let mut exits: Vec<Vec<u32>> = vec![Vec::new(); MAX_PLIES + 1];
+
+        for (idx, rem) in remaining.iter_mut().enumerate() {
+            let (squares, stm) = match table.decode(idx) {
+                Some(x) => x,
+                None => continue,
+            };
+            let pos = table.position(&squares[..table.pieces.len()], stm);
+            if pos.in_check(swap_color(stm)) {
+                continue;
+            }
+
+            let mut move_count = 0;
+            for mv in pos.gen_pseudo_legals(&ci) {
+                let mut new_pos = pos.clone();
+                if !new_pos.make_move(mv, &ci) {
+                    continue;
+                }
+                move_count += 1;
+                if new_pos.material_key() == table.key {
+                    continue;
+                }
+
+                // Captures and promotions leave the table, their values are already known
+                let value = match tables.probe_raw(&new_pos) {
+                    Some(value) => value,
+                    None if is_material_draw(&new_pos) => DRAW,
+                    None => {
+                        let code = tables.code_of(&new_pos);
+                        return Err(format!("{} requires the {} table", table.code(), code));
+                    }
+                };
+                if value >= LOSS {
+                    pending[2 * (value - LOSS) as usize + 1].push(idx as u32);
+                } else if value != DRAW {
+                    exits[2 * value as usize].push(idx as u32);
+                }
+            }
+
+            *rem = move_count;
+            if move_count == 0 && pos.in_check(stm) {
+                pending[0].push(idx as u32);
+            }
+        }
+
+        for ply in 0..MAX_PLIES {
+            let mut current = std::mem::take(&mut pending[ply]);
+            for idx in std::mem::take(&mut exits[ply]) {
+                remaining[idx as usize] -= 1;
+                if remaining[idx as usize] == 0 {
+                    current.push(idx);
+                }
+            }
+
+            for idx in current {
+                let idx = idx as usize;
+                if values[idx] != DRAW {
+                    continue;
+                }
+                values[idx] = plies_to_value(ply);
+
+                let (squares, stm) = table.decode(idx).unwrap();
+                table.predecessors(&squares, stm, |prev| {
+                    if values[prev] != DRAW {
+                        return;
+                    }
+                    // Any move into a lost position wins, a position is lost once all moves lose
+                    if ply % 2 == 0 {
+                        pending[ply + 1].push(prev as u32);
+                    } else {
+                        remaining[prev] -= 1;
+                        if remaining[prev] == 0 {
+                            pending[ply + 1].push(prev as u32);
+                        }
+                    }
+                });
+            }
+        }
+
+        table.data = values;
+        Ok(table)
+    }
+
+    pub fn write(&self, path: &Path) -> std::io::Result<()> {
+        let mut bytes = MAGIC.to_vec();
+        bytes.push(self.pieces.len() as u8);
+        bytes.extend_from_slice(&self.pieces);
+        bytes.extend_from_slice(&self.data);
+        std::fs::write(path, bytes)
+    }
+
+    fn read(path: &Path) -> Option<DtmTable> {
+        let bytes = std::fs::read(path).ok()?;
+        if bytes.len() < 5 || bytes[..4] != MAGIC {
+            return None;
+        }
+        let n = bytes[4] as usize;
+        let pieces = bytes.get(5..5 + n)?;
+        let mut table = DtmTable::new(&pieces_code(pieces))?;
+        if table.pieces != pieces {
+            return None;
+        }
+        table.data = bytes[5 + n..].to_vec();
+        if table.data.len() != table.size() {
+            return None;
+        }
+        Some(table)
+    }
+}
+
+// White pieces first, each side ordered from king to pawn
+fn pieces_code(pieces: &[Piece]) -> String {
+    let mut code = String::new();
+    for &c in [WHITE, BLACK].iter() {
+        for pt in (PAWN..=KING).rev() {
+            for _ in pieces.iter().filter(|&&p| p == make_piece(c, pt)) {
+                code.push(piecetype_to_char(pt).to_ascii_uppercase());
+            }
+        }
+    }
+    code
+}
+
+// Bare kings or a single minor piece can't mate
+fn is_material_draw(pos: &Position) -> bool {
+    let majors = pos.piecetype_bb(PAWN) | pos.piecetype_bb(ROOK) | pos.piecetype_bb(QUEEN);
+    let minors = pos.piecetype_bb(KNIGHT) | pos.piecetype_bb(BISHOP);
+    majors.is_empty() && minors.popcount() <= 1
+}
+
+#[derive(Default)]
+pub struct DtmTables {
+    tables: Vec<DtmTable>,
+    keys: HashMap<MaterialKey, usize>,
+}
+
+impl DtmTables {
+    // Paths are separated by ':' on unix and ';' on windows
+    pub fn init(paths: &str) -> DtmTables {
+        let mut tables = DtmTables::default();
+        let sep = if cfg!(windows) { ';' } else { ':' };
+        for dir in paths
+            .split(sep)
+            .filter(|x| !x.is_empty() && *x != "<empty>")
+        {
+            let entries = match std::fs::read_dir(dir) {
+                Ok(entries) => entries,
+                Err(_) => continue,
+            };
+            for path in entries.filter_map(|x| x.ok()).map(|x| x.path()) {
+                if path.extension().and_then(|x| x.to_str()) != Some("dtm") {
+                    continue;
+                }
+                if let Some(table) = DtmTable::read(&path) {
+                    tables.add(table);
+                }
+            }
+        }
+        tables
+    }
+
+    fn add(&mut self, table: DtmTable) {
+        if self.keys.contains_key(&table.key) {
+            return;
+        }
+        let code = table.code();
+        self.keys.insert(table.key, self.tables.len());
+        self.keys.insert(signature(&code, BLACK), self.tables.len());
+        self.tables.push(table);
+    }
+
+    pub fn len(&self) -> usize {
+        self.tables.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tables.is_empty()
+    }
+
+    fn code_of(&self, pos: &Position) -> String {
+        let mut code = String::new();
+        let strong = (pos.color_bb(BLACK).popcount() > pos.color_bb(WHITE).popcount()) as Color;
+        for &c in [strong, swap_color(strong)].iter() {
+            for pt in (PAWN..=KING).rev() {
+                for _ in pos.piece_bb(pt, c) {
+                    code.push(piecetype_to_char(pt).to_ascii_uppercase());
+                }
+            }
+        }
+        code
+    }
+
+    fn probe_raw(&self, pos: &Position) -> Option<u8> {
+        if pos.cr != 0 || pos.piecetype_bb(ALL).popcount() as usize > MAX_PIECES {
+            return None;
+        }
+        let &idx = self.keys.get(&pos.material_key())?;
+        let table = &self.tables[idx];
+        (table.pieces.len() == pos.piecetype_bb(ALL).popcount() as usize).then(|| table.probe(pos))
+    }
+
+    // Exact mate score relative to the side to move, None if there is no table for the position
+    pub fn probe(&self, pos: &Position, height: u8) -> Option<Score> {
+        let value = self.probe_raw(pos)?;
+        let height = height as Score;
+        Some(match value {
+            DRAW => 0,
+            v if v >= LOSS => -MATE + height + 2 * (v - LOSS) as Score,
+            v => MATE - height - (2 * v as Score - 1),
+        })
+    }
+}
+
+// Generates the given tables (all supported ones if none are given) and their dependencies
+pub fn gentb(args: &[String]) {
+    let dir = Path::new(args.first().map(|x| x.as_str()).unwrap_or("."));
+    let requested = if args.len() > 1 {
+        args[1..].iter().map(|x| x.to_uppercase()).collect()
+    } else {
+        DTM_TABLES
+            .iter()
+            .map(|x| x.0.to_owned())
+            .collect::<Vec<String>>()
+    };
+    if let Some(code) = requested
+        .iter()
+        .find(|x| !DTM_TABLES.iter().any(|t| t.0 == *x))
+    {
+        let supported = DTM_TABLES.iter().map(|x| x.0).collect::<Vec<&str>>();
+        println!(
+            "Unsupported table {}, supported are {}",
+            code,
+            supported.join(", ")
+        );
+        return;
+    }
+
+    let needed = |code: &str| {
+        requested
+            .iter()
+            .any(|x| x == code || DTM_TABLES.iter().any(|t| t.0 == x && t.1.contains(&code)))
+    };
+
+    let mut tables = DtmTables::default();
+    for &(code, _) in DTM_TABLES.iter().filter(|x| needed(x.0)) {
+        let start = Instant::now();
+        let table = match DtmTable::generate(code, &tables) {
+            Ok(table) => table,
+            Err(err) => return println!("{}", err),
+        };
+        let longest = table.data.iter().map(|&v| v & !LOSS).max().unwrap_or(0);
+        let path = dir.join(format!("{}.dtm", code));
+        if let Err(err) = table.write(&path) {
+            return println!("Failed to write {}: {}", path.display(), err);
+        }
+        println!(
+            "{}: longest mate {} moves, {} bytes, {} ms",
+            code,
+            longest,
+            table.data.len(),
+            start.elapsed().as_millis()
+        );
+        tables.add(table);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn longest_mate(table: &DtmTable) -> u8 {
+        table.data.iter().map(|&v| v & !LOSS).max().unwrap()
+    }
+
+    fn probe(tables: &DtmTables, fen: &str) -> Score {
+        tables
+            .probe(&Position::from_fen(fen).unwrap().0, 0)
+            .unwrap()
+    }
+
+    #[test]
+    fn invalid_codes() {
+        for code in ["KKKK", "KQ", "QKK", "KQKRR", "KXK", ""].iter() {
+            assert!(DtmTable::new(code).is_none(), "{}", code);
+        }
+        assert!(DtmTable::new("KQKR").is_some());
+    }
+
+    #[test]
+    fn kqk_and_krk() {
+        let mut tables = DtmTables::default();
+        for &(code, longest) in [("KQK", 10), ("KRK", 16)].iter() {
+            let table = DtmTable::generate(code, &tables).unwrap();
+            assert_eq!(longest_mate(&table), longest, "{}", code);
+            tables.add(table);
+        }
+
+        assert_eq!(probe(&tables, "k7/8/1K6/8/8/8/7Q/8 w - - 0 1"), MATE - 1);
+        assert_eq!(probe(&tables, "k7/1Q6/1K6/8/8/8/8/8 b - - 0 1"), -MATE);
+        assert_eq!(probe(&tables, "k7/8/8/8/8/8/8/KQ6 w - - 0 1"), MATE - 13);
+        assert_eq!(probe(&tables, "k7/8/8/8/8/8/8/KQ6 b - - 0 1"), -MATE + 14);
+        assert_eq!(probe(&tables, "8/8/8/4k3/8/8/8/R3K3 w - - 0 1"), MATE - 27);
+        // Black captures the hanging rook, colors are flipped for black pieces
+        assert_eq!(probe(&tables, "8/8/8/8/8/8/3kR3/6K1 b - - 0 1"), 0);
+        assert_eq!(probe(&tables, "8/8/8/8/8/8/3Kr3/6k1 w - - 0 1"), 0);
+        assert_eq!(probe(&tables, "K7/8/8/8/8/8/8/kq6 b - - 0 1"), MATE - 13);
+    }
+}
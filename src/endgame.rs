@@ -0,0 +1,296 @@
+use crate::bitbase;
+use crate::bitboard::*;
+use crate::position::Position;
+use crate::types::*;
+use crate::variant::Variant;
+
+// 4 bits per piece count, indexed by Piece. Kings are implied.
+pub type MaterialKey = u64;
+
+pub type ScaleFactor = i32;
+
+pub const SCALE_DRAW: ScaleFactor = 0;
+pub const SCALE_NORMAL: ScaleFactor = 64;
+
+pub const KNOWN_WIN: Score = 10000;
+
+#[derive(Clone, Copy)]
+pub enum EndgameFn {
+    // Replaces the evaluation, score is relative to the strong side
+    Value(fn(&Position, Color) -> Score),
+    // Scales the evaluation if it favours the strong side
+    Scale(fn(&Position, Color) -> ScaleFactor),
+}
+
+pub struct Endgame {
+    pub name: &'static str,
+    pub strong: Color,
+    pub func: EndgameFn,
+}
+
+// Computes the key of a signature like "KBNK", where the pieces in front of the second king belong
+// to the strong side
+pub const fn signature(code: &str, strong: Color) -> MaterialKey {
+    let code = code.as_bytes();
+    let mut key = 0;
+    let mut color = swap_color(strong);
+    let mut i = 0;
+    while i < code.len() {
+        let pt = match code[i] {
+            b'P' => PAWN,
+            b'N' => KNIGHT,
+            b'B' => BISHOP,
+            b'R' => ROOK,
+            b'Q' => QUEEN,
+            _ => KING,
+        };
+        if pt == KING {
+            color = swap_color(color);
+        } else {
+            key += 1 << (4 * make_piece(color, pt));
+        }
+        i += 1;
+    }
+    key
+}
+
+macro_rules! endgames {($ ($code: expr, $func: expr); +) => {[$(
+    (signature($code, WHITE), $code, WHITE, $func),
+    (signature($code, BLACK), $code, BLACK, $func),
+)+]};}
+
+#[rustfmt::skip]
+const ENDGAMES: [(MaterialKey, &str, Color, EndgameFn); 12] = endgames!(
+    "KPK", EndgameFn::Value(kpk);
+    "KBNK", EndgameFn::Value(kbnk);
+    "KNNK", EndgameFn::Value(knnk);
+    "KRKB", EndgameFn::Value(krkb);
+    "KRKN", EndgameFn::Value(krkn);
+    "KQKR", EndgameFn::Value(kqkr)
+);
+
+// Endgames which are not tied to a single material signature
+const MAX_ENDGAME_PIECES: u32 = 12;
+
+pub fn probe(pos: &Position) -> Option<Endgame> {
+    if pos.variant != Variant::Standard || pos.piecetype_bb(ALL).popcount() > MAX_ENDGAME_PIECES {
+        return None;
+    }
+
+    let key = pos.material_key();
+    for &(eg_key, name, strong, func) in ENDGAMES.iter() {
+        if eg_key == key {
+            return Some(Endgame { name, strong, func });
+        }
+    }
+
+    for &strong in [WHITE, BLACK].iter() {
+        let weak = swap_color(strong);
+        if pos.color_bb(weak).popcount() == 1 && has_mating_material(pos, strong) {
+            let func = EndgameFn::Value(kxk);
+            return Some(Endgame {
+                name: "KXK",
+                strong,
+                func,
+            });
+        }
+    }
+
+    for &strong in [WHITE, BLACK].iter() {
+        if is_kbpsk(pos, strong) {
+            let func = EndgameFn::Scale(kbpsk);
+            return Some(Endgame {
+                name: "KBPsK",
+                strong,
+                func,
+            });
+        }
+    }
+
+    if is_ocb(pos) {
+        let strong = (pos.piece_eval < 0) as Color;
+        let func = EndgameFn::Scale(ocb);
+        return Some(Endgame {
+            name: "OCB",
+            strong,
+            func,
+        });
+    }
+
+    None
+}
+
+fn non_pawn_material(pos: &Position, c: Color) -> BitBoard {
+    pos.color_bb(c) & !pos.piecetype_bb(PAWN) & !pos.piecetype_bb(KING)
+}
+
+fn has_mating_material(pos: &Position, c: Color) -> bool {
+    let bishops = pos.piece_bb(BISHOP, c);
+    pos.rook_likes_bb(c).not_empty()
+        || (bishops & DARK_SQUARES).not_empty() && (bishops & !DARK_SQUARES).not_empty()
+        || bishops.not_empty() && pos.piece_bb(KNIGHT, c).not_empty()
+}
+
+fn push_to_edge(sq: Square) -> Score {
+    90 - 30 * edge_distance(sq) as Score
+}
+
+fn push_close(sq1: Square, sq2: Square) -> Score {
+    140 - 20 * distance(sq1, sq2) as Score
+}
+
+fn push_away(sq1: Square, sq2: Square) -> Score {
+    120 - push_close(sq1, sq2)
+}
+
+// Material from the point of view of the strong side, using the PSQT evaluation
+fn material(pos: &Position, strong: Color) -> Score {
+    let eval = pos.piece_eval / crate::eval::DIV;
+    [eval, -eval][strong as usize] as Score
+}
+
+fn kxk(pos: &Position, strong: Color) -> Score {
+    let (strong_k, weak_k) = (pos.king_sq(strong), pos.king_sq(swap_color(strong)));
+    let score = material(pos, strong) + push_to_edge(weak_k) * 4 + push_close(strong_k, weak_k) * 2;
+    (KNOWN_WIN + score).min(TB_WIN_IN_MAX - 1)
+}
+
+// The weak king has to be driven into a corner of the bishop's color
+fn kbnk(pos: &Position, strong: Color) -> Score {
+    let (strong_k, weak_k) = (pos.king_sq(strong), pos.king_sq(swap_color(strong)));
+    let corners = if (pos.piece_bb(BISHOP, strong) & DARK_SQUARES).not_empty() {
+        [A1, H8]
+    } else {
+        [A8, H1]
+    };
+    let corner_dist = distance(weak_k, corners[0]).min(distance(weak_k, corners[1]));
+    KNOWN_WIN + push_close(strong_k, weak_k) + 60 * (7 - corner_dist as Score)
+}
+
+fn kpk(pos: &Position, strong: Color) -> Score {
+    let score = bitbase::probe_kpk(pos).unwrap();
+    if pos.ctm == strong {
+        score
+    } else {
+        -score
+    }
+}
+
+fn knnk(_: &Position, _: Color) -> Score {
+    0
+}
+
+fn krkb(pos: &Position, strong: Color) -> Score {
+    push_to_edge(pos.king_sq(swap_color(strong)))
+}
+
+fn krkn(pos: &Position, strong: Color) -> Score {
+    let weak = swap_color(strong);
+    let (weak_k, weak_n) = (pos.king_sq(weak), pos.piece_bb(KNIGHT, weak).lsb());
+    push_to_edge(weak_k) + push_away(weak_k, weak_n)
+}
+
+fn kqkr(pos: &Position, strong: Color) -> Score {
+    let (strong_k, weak_k) = (pos.king_sq(strong), pos.king_sq(swap_color(strong)));
+    material(pos, strong) + push_to_edge(weak_k) + push_close(strong_k, weak_k)
+}
+
+// A bishop and rook pawns on a single file, where the bishop can't control the promotion square
+fn is_kbpsk(pos: &Position, strong: Color) -> bool {
+    let weak = swap_color(strong);
+    let pawns = pos.piece_bb(PAWN, strong);
+    non_pawn_material(pos, strong) == pos.piece_bb(BISHOP, strong)
+        && pos.piece_bb(BISHOP, strong).popcount() == 1
+        && pawns.not_empty()
+        && ((pawns & !FILE_A_BB).is_empty() || (pawns & !FILE_H_BB).is_empty())
+        && non_pawn_material(pos, weak).is_empty()
+}
+
+fn kbpsk(pos: &Position, strong: Color) -> ScaleFactor {
+    let weak = swap_color(strong);
+    let file = file_of(pos.piece_bb(PAWN, strong).lsb());
+    let queening_sq = to_square(relative_rank(RANK_8, strong), file);
+    let bishop_dark = (pos.piece_bb(BISHOP, strong) & DARK_SQUARES).not_empty();
+    let queening_dark = (bb!(queening_sq) & DARK_SQUARES).not_empty();
+    if bishop_dark != queening_dark && distance(queening_sq, pos.king_sq(weak)) <= 1 {
+        SCALE_DRAW
+    } else {
+        SCALE_NORMAL
+    }
+}
+
+// Both sides only have a single bishop and pawns, with the bishops on opposite colors
+fn is_ocb(pos: &Position) -> bool {
+    let bishops = pos.piecetype_bb(BISHOP);
+    [WHITE, BLACK].iter().all(|&c| {
+        non_pawn_material(pos, c) == pos.piece_bb(BISHOP, c)
+            && pos.piece_bb(BISHOP, c).popcount() == 1
+    }) && (bishops & DARK_SQUARES).popcount() == 1
+}
+
+fn ocb(pos: &Position, strong: Color) -> ScaleFactor {
+    let weak = swap_color(strong);
+    let pawn_diff =
+        pos.piece_bb(PAWN, strong).popcount() as i32 - pos.piece_bb(PAWN, weak).popcount() as i32;
+    if pawn_diff <= 1 {
+        SCALE_NORMAL / 4
+    } else {
+        SCALE_NORMAL / 2
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::eval::eval;
+
+    fn scale(fen: &str) -> ScaleFactor {
+        let (pos, _) = Position::from_fen(fen).unwrap();
+        match probe(&pos) {
+            Some(Endgame {
+                strong,
+                func: EndgameFn::Scale(f),
+                ..
+            }) => f(&pos, strong),
+            _ => panic!("no scaling endgame for {}", fen),
+        }
+    }
+
+    #[test]
+    fn knnk_is_drawn() {
+        let (pos, _) = Position::from_fen("8/8/8/4k3/8/8/8/2NNK3 w - - 0 1").unwrap();
+        assert_eq!(eval(&pos), 0);
+    }
+
+    #[test]
+    fn kbnk_drives_to_bishop_corner() {
+        // Dark squared bishop, the black king walks towards a1
+        let fens = [
+            "4K3/8/7B/8/3k4/8/8/7N w - - 0 1",
+            "4K3/8/7B/8/8/2k5/8/7N w - - 0 1",
+            "4K3/8/7B/8/8/8/1k6/7N w - - 0 1",
+            "4K3/8/7B/8/8/8/8/k6N w - - 0 1",
+        ];
+        let scores = fens
+            .iter()
+            .map(|fen| eval(&Position::from_fen(fen).unwrap().0))
+            .collect::<Vec<Score>>();
+        assert!(scores.windows(2).all(|w| w[0] < w[1]), "{:?}", scores);
+        // The light corner is no mating corner
+        let (pos, _) = Position::from_fen("k3K3/8/7B/8/8/8/8/7N w - - 0 1").unwrap();
+        assert!(eval(&pos) < scores[3]);
+    }
+
+    #[test]
+    fn draw_scales() {
+        // Opposite colored bishops with one extra pawn
+        assert_eq!(
+            scale("8/4kb2/8/3p4/3P4/2P5/4K3/2B5 w - - 0 1"),
+            SCALE_NORMAL / 4
+        );
+        // The dark squared bishop can't drive the king from the light a8 corner
+        assert_eq!(scale("1k6/8/8/8/8/P7/8/K1B5 w - - 0 1"), SCALE_DRAW);
+        assert_eq!(scale("8/8/8/5k2/8/P7/8/K1B5 w - - 0 1"), SCALE_NORMAL);
+        assert_eq!(scale("1k6/8/8/8/8/P7/8/K4B2 w - - 0 1"), SCALE_NORMAL);
+    }
+}
@@ -0,0 +1,280 @@
+use crate::history::HashHist;
+use crate::position::{CastleInfo, FenError, Position};
+use crate::r#move::*;
+use crate::search::{start_search, Limits};
+use crate::thread::{SharedState, Thread};
+use crate::types::*;
+
+use std::fmt;
+use std::sync::atomic::Ordering;
+use std::time::Instant;
+
+/*
+An EPD record consists of the first four FEN fields followed by operations, each being an opcode
+and its operands terminated by a semicolon, e.g.
+  2rr3k/pp3pp1/1nnqbN1p/3pN3/2pP4/2P3Q1/PPB4P/R4RK1 w - - bm Qg6; id "WAC.001";
+Operands containing whitespace or semicolons are quoted.
+*/
+
+const DEFAULT_MOVETIME: u128 = 1000;
+
+#[derive(Debug)]
+pub enum EpdError {
+    InvalidFen(FenError),
+    InvalidOperation(String),
+    InvalidOperand(String, String),
+}
+
+impl fmt::Display for EpdError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EpdError::InvalidFen(err) => write!(f, "invalid FEN: {}", err),
+            EpdError::InvalidOperation(s) => write!(f, "invalid operation '{}'", s),
+            EpdError::InvalidOperand(op, s) => write!(f, "invalid operand '{}' for {}", s, op),
+        }
+    }
+}
+
+pub struct Epd {
+    pub pos: Position,
+    pub ci: CastleInfo,
+    pub ops: Vec<(String, Vec<String>)>,
+}
+
+impl Epd {
+    pub fn parse(line: &str) -> Result<Epd, EpdError> {
+        let line = line.trim();
+        let mut fields = line.splitn(5, char::is_whitespace);
+        let fen = fields.by_ref().take(4).collect::<Vec<&str>>().join(" ");
+        let (pos, ci) = Position::from_fen(&fen).map_err(EpdError::InvalidFen)?;
+
+        let mut ops = Vec::new();
+        let mut tokens = Vec::new();
+        let mut chars = fields.next().unwrap_or("").chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                ';' => {
+                    if tokens.is_empty() {
+                        return Err(EpdError::InvalidOperation(String::new()));
+                    }
+                    let opcode = tokens.remove(0);
+                    ops.push((opcode, std::mem::take(&mut tokens)));
+                }
+                '"' => tokens.push(chars.by_ref().take_while(|&c| c != '"').collect()),
+                c if c.is_whitespace() => {}
+                c => {
+                    let mut token = c.to_string();
+                    while let Some(&c) = chars.peek() {
+                        if c.is_whitespace() || c == ';' {
+                            break;
+                        }
+                        token.push(c);
+                        chars.next();
+                    }
+                    tokens.push(token);
+                }
+            }
+        }
+        if !tokens.is_empty() {
+            return Err(EpdError::InvalidOperation(tokens.join(" ")));
+        }
+
+        Ok(Epd { pos, ci, ops })
+    }
+
+    pub fn operands(&self, opcode: &str) -> Option<&[String]> {
+        self.ops
+            .iter()
+            .find(|x| x.0 == opcode)
+            .map(|x| x.1.as_slice())
+    }
+
+    pub fn id(&self) -> Option<&str> {
+        self.operands("id")?.first().map(|x| x.as_str())
+    }
+
+    pub fn comment(&self) -> Option<&str> {
+        self.operands("c0")?.first().map(|x| x.as_str())
+    }
+
+    fn moves(&self, opcode: &str) -> Result<Vec<Move>, EpdError> {
+        let invalid = |s: &String| EpdError::InvalidOperand(opcode.to_owned(), s.to_owned());
+        self.operands(opcode)
+            .unwrap_or(&[])
+            .iter()
+            .map(|s| Move::from_san(&self.pos, &self.ci, s).ok_or_else(|| invalid(s)))
+            .collect()
+    }
+
+    pub fn best_moves(&self) -> Result<Vec<Move>, EpdError> {
+        self.moves("bm")
+    }
+
+    pub fn avoid_moves(&self) -> Result<Vec<Move>, EpdError> {
+        self.moves("am")
+    }
+
+    fn number<T: std::str::FromStr>(&self, opcode: &str) -> Result<Option<T>, EpdError> {
+        match self.operands(opcode).and_then(|x| x.first()) {
+            Some(s) => s
+                .parse()
+                .map(Some)
+                .map_err(|_| EpdError::InvalidOperand(opcode.to_owned(), s.to_owned())),
+            None => Ok(None),
+        }
+    }
+
+    // Direct mate in the given number of moves
+    pub fn direct_mate(&self) -> Result<Option<i32>, EpdError> {
+        self.number("dm")
+    }
+
+    pub fn analysis_depth(&self) -> Result<Option<u8>, EpdError> {
+        self.number("acd")
+    }
+}
+
+impl fmt::Display for Epd {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let fen = self.pos.to_fen(&self.ci);
+        let fields = fen.split_whitespace().take(4).collect::<Vec<&str>>();
+        write!(f, "{}", fields.join(" "))?;
+        for (opcode, operands) in self.ops.iter() {
+            write!(f, " {}", opcode)?;
+            for operand in operands.iter() {
+                if operand.contains(|c: char| c.is_whitespace() || c == ';') || opcode == "id" {
+                    write!(f, " \"{}\"", operand)?;
+                } else {
+                    write!(f, " {}", operand)?;
+                }
+            }
+            write!(f, ";")?;
+        }
+        Ok(())
+    }
+}
+
+// Checks the search result against the bm, am and dm operations, None if there is nothing to check
+fn is_solved(epd: &Epd, mv: Move, score: Score) -> Result<Option<bool>, EpdError> {
+    let (best, avoid, mate) = (epd.best_moves()?, epd.avoid_moves()?, epd.direct_mate()?);
+    if best.is_empty() && avoid.is_empty() && mate.is_none() {
+        return Ok(None);
+    }
+    let mate_found =
+        mate.is_none_or(|dm| score > MATE_IN_MAX && ((MATE - score) as i32 / 2 + 1) <= dm);
+    Ok(Some(
+        (best.is_empty() || best.contains(&mv)) && !avoid.contains(&mv) && mate_found,
+    ))
+}
+
+// Runs an EPD test suite: epdtest <file> [depth <n>] [movetime <ms>] [nodes <n>]
+pub fn epdtest(args: &[String]) {
+    let file = match args.first() {
+        Some(file) => file,
+        None => return println!("Usage: epdtest <file> [depth <n>] [movetime <ms>] [nodes <n>]"),
+    };
+    let content = match std::fs::read_to_string(file) {
+        Ok(content) => content,
+        Err(err) => return println!("Failed to read {}: {}", file, err),
+    };
+
+    let mut template = Limits {
+        is_silent: true,
+        ..Default::default()
+    };
+    let mut tokens = args[1..].iter();
+    while let Some(token) = tokens.next() {
+        let value = tokens.next().map(|x| x.as_str()).unwrap_or("");
+        match token.as_str() {
+            "depth" => template.depth = value.parse().unwrap_or(template.depth),
+            "movetime" => template.movetime = value.parse().unwrap_or(DEFAULT_MOVETIME),
+            "nodes" => template.nodes = value.parse().unwrap_or(0),
+            _ => return println!("Unknown limit {}", token),
+        }
+    }
+    if !args[1..]
+        .iter()
+        .any(|x| ["depth", "movetime", "nodes"].contains(&x.as_str()))
+    {
+        template.movetime = DEFAULT_MOVETIME;
+    }
+    template.spend = template.movetime;
+    template.is_time_limit = template.movetime != 0;
+
+    let mut shared_state = SharedState::default();
+    shared_state.launch_threads(1);
+
+    let start = Instant::now();
+    let (mut solved, mut total) = (0, 0);
+    let lines = content
+        .lines()
+        .filter(|x| !x.trim().is_empty() && !x.starts_with('#'));
+    for (i, line) in lines.enumerate() {
+        let epd = match Epd::parse(line) {
+            Ok(epd) => epd,
+            Err(err) => {
+                println!("{:>4} invalid EPD: {}", i + 1, err);
+                continue;
+            }
+        };
+
+        // Every position is searched from scratch, as after ucinewgame
+        shared_state.abort.store(false, Ordering::Relaxed);
+        shared_state.reset_nodes();
+        shared_state.clear_tt();
+        let limits = Limits {
+            start: Instant::now(),
+            ..template.clone()
+        };
+        let mut hist = HashHist::default();
+        hist.push(&epd.pos);
+        let (pos, ci) = (epd.pos.clone(), epd.ci.clone());
+        let mut thread = Thread::new(&shared_state, 0, pos, ci, hist, limits);
+        start_search(&mut thread);
+
+        let (mv, score) = (thread.best_move, thread.best_score);
+        let id = epd.id().unwrap_or("-");
+        // The search can be stopped before depth 1 is finished
+        let san = if mv == NO_MOVE {
+            "-".to_owned()
+        } else {
+            mv.to_san(&epd.pos, &epd.ci)
+        };
+        let expected = epd
+            .ops
+            .iter()
+            .filter(|x| ["bm", "am", "dm"].contains(&x.0.as_str()))
+            .map(|x| format!("{} {}", x.0, x.1.join(" ")))
+            .collect::<Vec<String>>()
+            .join(", ");
+        let result = match is_solved(&epd, mv, score) {
+            Ok(Some(_)) if mv == NO_MOVE => "failed",
+            Ok(Some(true)) => "solved",
+            Ok(Some(false)) => "failed",
+            Ok(None) => "unscored",
+            Err(err) => {
+                println!("{:>4} {:<16} {}", i + 1, id, err);
+                continue;
+            }
+        };
+        if result != "unscored" {
+            total += 1;
+            solved += (result == "solved") as usize;
+        }
+        println!(
+            "{:>4} {:<16} {:<8} {:<8} {}",
+            i + 1,
+            id,
+            result,
+            san,
+            expected
+        );
+    }
+
+    let time = start.elapsed().as_secs_f64();
+    let percentage = 100.0 * solved as f64 / (total as f64).max(1.0);
+    println!(
+        "\nSolved {}/{} ({:.1}%) - Time {:.3}",
+        solved, total, percentage, time
+    );
+}
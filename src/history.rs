@@ -2,41 +2,39 @@ use crate::position::Position;
 
 use std::cmp::min;
 
-#[derive(Clone)]
+#[derive(Clone, Default)]
 pub struct HashHist {
-    hist: [u64; 256],
-    pointer: usize,
-}
-
-impl Default for HashHist {
-    fn default() -> Self {
-        HashHist {
-            hist: [0; 256],
-            pointer: 0,
-        }
-    }
+    hist: Vec<u64>,
 }
 
 impl HashHist {
     pub fn clear(&mut self) {
-        self.pointer = 0;
+        self.hist.clear();
+    }
+
+    pub fn len(&self) -> usize {
+        self.hist.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.hist.is_empty()
     }
 
     pub fn push(&mut self, pos: &Position) {
-        self.hist[self.pointer] = pos.hash;
-        self.pointer += 1;
+        self.hist.push(pos.hash);
     }
 
     pub fn pop(&mut self) {
-        self.pointer -= 1;
+        self.hist.pop();
     }
 
     pub fn is_2fold(&self, pos: &Position) -> bool {
-        //hist[self.pointer -1] = pos.hash
-        //hist[self.pointer -3] = pos_2movesago.hash != pos.hash
+        //hist[len - 1] = pos.hash
+        //hist[len - 3] = pos_2movesago.hash != pos.hash
         //=> Start at 5, stride 2
-        for i in (5..=min(self.pointer, 1 + pos.mr50 as usize)).step_by(2) {
-            if self.hist[self.pointer - i] == pos.hash {
+        let len = self.hist.len();
+        for i in (5..=min(len, 1 + pos.mr50 as usize)).step_by(2) {
+            if self.hist[len - i] == pos.hash {
                 return true;
             }
         }
@@ -1,15 +1,27 @@
 #[macro_use]
 pub mod bitboard;
+pub mod analyze;
 pub mod attacks;
 pub mod bench;
+pub mod bitbase;
+pub mod book;
+pub mod datagen;
+pub mod dtm;
+pub mod endgame;
+pub mod epd;
 pub mod eval;
 pub mod history;
 pub mod magics;
 pub mod r#move;
 pub mod movepicker;
 pub mod perft;
+pub mod pgn;
 pub mod position;
+pub mod rng;
 pub mod search;
+pub mod syzygy;
 pub mod thread;
+pub mod tournament;
 pub mod transposition;
 pub mod types;
+pub mod variant;
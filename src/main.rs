@@ -3,6 +3,7 @@ use scam::position::{CastleInfo, Position};
 use scam::r#move::Move;
 use scam::thread::SharedState;
 use scam::types::*;
+use scam::variant::{Variant, VARIANTS};
 use scam::*;
 
 use std::io::{prelude::*, stdin};
@@ -12,8 +13,17 @@ fn uci() {
     println!("id name Scam 0.4");
     println!("id author Fabian von der Warth, Terje Kirstihagen");
     println!("option name UCI_Chess960 type check default false");
+    let vars = VARIANTS
+        .iter()
+        .map(|v| format!(" var {}", v.name()))
+        .collect::<String>();
+    println!("option name UCI_Variant type combo default chess{}", vars);
     println!("option name Threads type spin default 1 min 1 max 65536");
     println!("option name Hash type spin default 2 min 1 max 2147483647");
+    println!("option name SyzygyPath type string default <empty>");
+    println!("option name DTMPath type string default <empty>");
+    println!("option name OwnBook type check default false");
+    println!("option name BookFile type string default <empty>");
     println!("uciok")
 }
 
@@ -29,6 +39,9 @@ fn go(
     let c = pos.ctm;
     let overhead = 5;
 
+    // The book only replaces normal timed or depth limited searches
+    let mut use_book = true;
+
     macro_rules! value {
         () => {
             tokens.next().unwrap().parse().unwrap()
@@ -37,7 +50,11 @@ fn go(
 
     while let Some(content) = tokens.next() {
         match content {
-            "infinite" => limits.is_infinite = true,
+            "infinite" => {
+                limits.is_infinite = true;
+                use_book = false;
+            }
+            "ponder" | "searchmoves" => use_book = false,
             "wtime" if c == WHITE => limits.time = value!(),
             "btime" if c == BLACK => limits.time = value!(),
             "winc" if c == WHITE => limits.inc = value!(),
@@ -45,11 +62,24 @@ fn go(
             "movestogo" => limits.moves_to_go = value!(),
             "movetime" => limits.movetime = value!(),
             "depth" => limits.depth = value!(),
-            "mate" => limits.mate = value!(),
+            "mate" => {
+                limits.mate = value!();
+                use_book = false;
+            }
+            "nodes" => {
+                limits.nodes = value!();
+                use_book = false;
+            }
             _ => {}
         }
     }
 
+    if use_book && shared_state.own_book && pos.variant == Variant::Standard {
+        if let Some(mv) = shared_state.book.as_ref().and_then(|x| x.probe(pos, ci)) {
+            return println!("bestmove {}", mv.to_str(ci));
+        }
+    }
+
     limits.is_time_limit = limits.time != 0 || limits.movetime != 0;
 
     // Basic time management for now
@@ -61,41 +91,83 @@ fn go(
     shared_state.start_search(pos.clone(), ci.clone(), hist.clone(), limits);
 }
 
-fn position(pos: &mut Position, ci: &mut CastleInfo, hist: &mut HashHist, line: String) {
+fn position(
+    pos: &mut Position,
+    ci: &mut CastleInfo,
+    hist: &mut HashHist,
+    chess960: bool,
+    variant: Variant,
+    line: String,
+) {
     let (newpos, newci) = if line.contains("fen") {
-        Position::parse_fen(line.splitn(3, ' ').nth(2).unwrap())
+        let fen = line.splitn(3, ' ').nth(2).unwrap_or("");
+        match Position::from_variant_fen(fen.split(" moves").next().unwrap(), variant) {
+            Ok(x) => x,
+            Err(err) => return println!("info string Invalid FEN: {}", err),
+        }
+    } else if line.contains("frc") {
+        // position frc <n> or position dfrc <white n> <black n>, numbered 0 to 959
+        let numbers = line
+            .split_whitespace()
+            .skip(2)
+            .take_while(|&x| x != "moves")
+            .map(|x| x.parse::<u16>().ok().filter(|&n| n < 960))
+            .collect::<Option<Vec<u16>>>();
+        match (line.contains("dfrc"), numbers.as_deref()) {
+            (false, Some(&[n])) => Position::frc_startpos(n),
+            (true, Some(&[white, black])) => Position::dfrc_startpos(white, black),
+            _ => return println!("info string Invalid FRC start position number"),
+        }
     } else {
-        Position::startpos()
+        Position::from_variant_fen(variant.start_fen(), variant).unwrap()
     };
 
     *pos = newpos;
     *ci = newci;
+    // FRC notation is kept if the GUI asked for it or the position can't be expressed otherwise
+    ci.frc |= chess960;
     hist.clear();
     hist.push(pos);
 
     if line.contains("moves ") {
-        line.rsplit("moves ")
-            .next()
-            .unwrap()
-            .split_whitespace()
-            .for_each(|m| {
-                assert!(pos.make_move(Move::from_str(pos, ci, m), &ci));
-                if pos.mr50 == 0 {
-                    hist.clear();
-                }
-                hist.push(pos);
-            });
+        for m in line.rsplit("moves ").next().unwrap().split_whitespace() {
+            match Move::from_str(pos, ci, m) {
+                Some(mv) if pos.make_move(mv, ci) => {}
+                _ => return println!("info string Illegal move: {}", m),
+            }
+            if pos.mr50 == 0 {
+                hist.clear();
+            }
+            hist.push(pos);
+        }
     }
 }
 
-fn setoption(line: String, ci: &mut CastleInfo, shared_state: &mut SharedState) {
+fn setoption(
+    line: String,
+    ci: &mut CastleInfo,
+    chess960: &mut bool,
+    variant: &mut Variant,
+    shared_state: &mut SharedState,
+) {
     let mut iter = line.rsplit("name ").next().unwrap().split(" value ");
     let name = iter.next().unwrap();
-    let value = iter.next().unwrap();
+    let value = iter.next().unwrap_or("");
     match name {
-        "UCI_Chess960" => ci.frc = value.parse().unwrap(),
+        "UCI_Chess960" => {
+            *chess960 = value.parse().unwrap();
+            ci.frc |= *chess960;
+        }
+        "UCI_Variant" => match Variant::from_name(value) {
+            Some(v) => *variant = v,
+            None => println!("info string Unsupported variant {}", value),
+        },
         "Threads" => shared_state.launch_threads(value.parse().unwrap()),
         "Hash" => shared_state.reallocate_tt(value.parse().unwrap()),
+        "SyzygyPath" => shared_state.load_tablebases(value),
+        "DTMPath" => shared_state.load_dtm_tables(value),
+        "OwnBook" => shared_state.own_book = value.parse().unwrap(),
+        "BookFile" => shared_state.load_book(value),
         _ => println!("Unrecognized option: {}!", name),
     }
 }
@@ -105,10 +177,24 @@ fn main() {
         return scam::bench::bench();
     } else if std::env::args().nth(1) == Some("perftbench".to_owned()) {
         return scam::bench::perftbench();
+    } else if std::env::args().nth(1) == Some("gentb".to_owned()) {
+        return scam::dtm::gentb(&std::env::args().skip(2).collect::<Vec<String>>());
+    } else if std::env::args().nth(1) == Some("epdtest".to_owned()) {
+        return scam::epd::epdtest(&std::env::args().skip(2).collect::<Vec<String>>());
+    } else if std::env::args().nth(1) == Some("makebook".to_owned()) {
+        return scam::book::makebook(&std::env::args().skip(2).collect::<Vec<String>>());
+    } else if std::env::args().nth(1) == Some("datagen".to_owned()) {
+        return scam::datagen::datagen(&std::env::args().skip(2).collect::<Vec<String>>());
+    } else if std::env::args().nth(1) == Some("match".to_owned()) {
+        return scam::tournament::run_match(&std::env::args().skip(2).collect::<Vec<String>>());
+    } else if std::env::args().nth(1) == Some("analyze".to_owned()) {
+        return scam::analyze::analyze(&std::env::args().skip(2).collect::<Vec<String>>());
     }
 
     let (mut pos, mut ci) = Position::startpos();
     let mut hist = HashHist::default();
+    let mut chess960 = false;
+    let mut variant = Variant::Standard;
     let mut shared_state = SharedState::default();
     shared_state.launch_threads(1);
 
@@ -118,16 +204,49 @@ fn main() {
             "go" => go(&pos, &ci, &hist, &mut shared_state, line),
             "uci" => uci(),
             "isready" => println!("readyok"),
-            "setoption" => setoption(line, &mut ci, &mut shared_state),
-            "position" => position(&mut pos, &mut ci, &mut hist, line),
+            "ucinewgame" => shared_state.clear_tt(),
+            "setoption" => setoption(
+                line,
+                &mut ci,
+                &mut chess960,
+                &mut variant,
+                &mut shared_state,
+            ),
+            "position" => position(&mut pos, &mut ci, &mut hist, chess960, variant, line),
             "stop" => shared_state.abort.store(true, Ordering::Relaxed),
             "quit" => break,
             // Non-UCI commands
-            "eval" => println!("{}", eval::eval(&pos)),
-            "perft" => perft::perft(line),
+            "eval" | "trace" => println!("{}", eval::trace(&pos)),
+            "perft" => perft::perft(line, variant),
             "bench" => scam::bench::bench(),
             "print" => println!("{}", pos),
+            "fen" => println!("{}", pos.to_fen(&ci)),
             _ => {}
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn long_move_list() {
+        // Knights shuffle back and forth without a pawn move or capture for 1200 plies
+        let knight_moves = [["g1f3", "f3g1"], ["g8f6", "f6g8"]];
+        let moves = (0..1200)
+            .map(|ply| knight_moves[ply % 2][ply / 2 % 2])
+            .collect::<Vec<&str>>();
+
+        let (mut pos, mut ci) = Position::startpos();
+        let mut hist = HashHist::default();
+        let line = format!("position startpos moves {}", moves.join(" "));
+        position(&mut pos, &mut ci, &mut hist, false, Variant::Standard, line);
+        // Counters and the history go well past what fits into a byte
+        assert_eq!(pos.mr50, 1200);
+        assert_eq!(pos.fullmove, 601);
+        assert_eq!(hist.len(), 1201);
+        assert!(pos.to_fen(&ci).ends_with(" 1200 601"));
+        assert!(hist.is_2fold(&pos));
+    }
+}
@@ -2,29 +2,30 @@ use crate::attacks::*;
 use crate::bitboard::*;
 use crate::position::{CastleInfo, Position};
 use crate::types::*;
+use crate::variant::Variant;
 
-/* u16 Move construction
-0000 0000 0011 1111 -> to square
-0000 1111 1100 0000 -> from square
-0011 0000 0000 0000 -> promotion type
-1100 0000 0000 0000 -> move type
+/* u32 Move construction, the promotion type needs 3 bits for antichess king promotions
+0000 0000 0000 0000 0011 1111 -> to square
+0000 0000 0000 1111 1100 0000 -> from square
+0000 0000 0111 0000 0000 0000 -> promotion type
+0000 0001 1000 0000 0000 0000 -> move type
 */
 
-pub type MoveType = u16;
+pub type MoveType = u32;
 pub const NORMAL: MoveType = 0;
-pub const PROMOTION: MoveType = 1 << 14;
-pub const ENPASSANT: MoveType = 2 << 14;
-pub const CASTLING: MoveType = 3 << 14;
+pub const PROMOTION: MoveType = 1 << 15;
+pub const ENPASSANT: MoveType = 2 << 15;
+pub const CASTLING: MoveType = 3 << 15;
 pub const NO_MOVE: Move = Move(0);
 
 #[derive(Clone, Copy, PartialEq, Eq, Default)]
-pub struct Move(u16);
+pub struct Move(u32);
 
 impl Move {
     pub fn new(from: Square, to: Square, mt: MoveType, promo: Option<PieceType>) -> Self {
         debug_assert!(mt == PROMOTION && promo.is_some() || mt != PROMOTION && promo.is_none());
         let p = promo.unwrap_or(KNIGHT);
-        Move(mt | (((p - KNIGHT) as u16) << 12) | (from << 6) as u16 | to as u16)
+        Move(mt | (((p - KNIGHT) as u32) << 12) | (from << 6) | to)
     }
 
     pub const fn to(self) -> Square {
@@ -40,12 +41,12 @@ impl Move {
     }
 
     pub const fn move_type(self) -> MoveType {
-        (self.0 & (3 << 14)) as MoveType
+        self.0 & (3 << 15)
     }
 
     pub fn promo_type(self) -> PieceType {
         debug_assert_eq!(self.move_type(), PROMOTION);
-        ((self.0 >> 12) & 3) as PieceType + KNIGHT
+        ((self.0 >> 12) & 7) as PieceType + KNIGHT
     }
 
     pub fn is_pseudolegal(&self, pos: &Position, ci: &CastleInfo) -> bool {
@@ -60,6 +61,18 @@ impl Move {
 
         let from_piece = pos.piece_on(from).unwrap();
 
+        if self.move_type() == PROMOTION && !pos.variant.promo_types().contains(&self.promo_type())
+        {
+            return false;
+        }
+        // Captures are mandatory in antichess
+        if pos.variant == Variant::Antichess
+            && pos.is_quiet(*self)
+            && !pos.gen_captures(ci).is_empty()
+        {
+            return false;
+        }
+
         if piecetype_of(from_piece) == PAWN {
             return if self.move_type() == ENPASSANT {
                 pos.ep == to
@@ -70,7 +83,7 @@ impl Move {
                 }
                 let enemies = pos.color_bb(swap_color(color));
                 let push = pawn_push(from_bb, color, occ);
-                let double = pawn_push(push & RANK_BB[relative_rank(RANK_3, color)], color, occ);
+                let double = pawn_push(push & pos.double_push_bb(color), color, occ);
                 ((push | double | (pawn_attack_bb(from, color) & enemies)) & to_bb).not_empty()
             };
         } else if self.move_type() == NORMAL {
@@ -90,21 +103,31 @@ impl Move {
         false
     }
 
-    pub fn from_str(pos: &Position, ci: &CastleInfo, s: &str) -> Move {
-        for m in pos.gen_pseudo_legals(ci) {
-            if m.to_str(ci) == s {
-                return m;
-            }
-        }
-
-        panic!("Invalid movestring given.")
+    // Castling is accepted both as king-to-target and as king-takes-rook, whatever the mode
+    pub fn from_str(pos: &Position, ci: &CastleInfo, s: &str) -> Option<Move> {
+        let moves = pos.gen_legals(ci).collect::<Vec<Move>>();
+        moves
+            .iter()
+            .find(|m| m.to_uci(ci.frc) == s)
+            .or_else(|| {
+                moves
+                    .iter()
+                    .find(|m| m.move_type() == CASTLING && m.to_uci(!ci.frc) == s)
+            })
+            .copied()
     }
 
     pub fn to_str(self, ci: &CastleInfo) -> String {
+        self.to_uci(ci.frc)
+    }
+
+    fn to_uci(self, frc: bool) -> String {
         let from = square_to_str(self.from());
-        let to = if self.move_type() == CASTLING && !ci.frc {
-            let to = (bb!(self.to()).shift(WEST) | bb!(self.to()).shift(EAST + EAST)).lsb();
-            square_to_str(to)
+        let to = if self.move_type() == CASTLING && !frc {
+            // The king's destination, the move itself stores the rook square
+            let color = (rank_of(self.from()) == RANK_8) as usize;
+            let cr = [[W_QS, W_KS], [B_QS, B_KS]][color][(self.to() > self.from()) as usize];
+            square_to_str(CASTLE_K_TARGET[cr as usize])
         } else {
             square_to_str(self.to())
         };
@@ -115,6 +138,135 @@ impl Move {
         };
         format!("{}{}{}", from, to, promo)
     }
+
+    pub fn to_san(self, pos: &Position, ci: &CastleInfo) -> String {
+        let (from, to) = (self.from(), self.to());
+        let pt = piecetype_of(pos.moved_piece(self));
+        let is_capture = pos.is_capture(self);
+
+        let mut san = String::new();
+        if self.move_type() == CASTLING {
+            san.push_str(if to < from { "O-O-O" } else { "O-O" });
+        } else if pt == PAWN {
+            if is_capture {
+                san.push(file_to_char(file_of(from)));
+                san.push('x');
+            }
+            san.push_str(&square_to_str(to));
+            if self.move_type() == PROMOTION {
+                san.push('=');
+                san.push(piecetype_to_char(self.promo_type()).to_ascii_uppercase());
+            }
+        } else {
+            san.push(piecetype_to_char(pt).to_ascii_uppercase());
+
+            // Other pieces of the same type which can legally move to the same square
+            let others = pos
+                .gen_legals(ci)
+                .filter(|m| m.to() == to && m.from() != from && m.move_type() != CASTLING)
+                .filter(|m| pos.piece_on(m.from()) == pos.piece_on(from))
+                .map(|m| m.from())
+                .collect::<Vec<Square>>();
+            if !others.is_empty() {
+                if others.iter().all(|&sq| file_of(sq) != file_of(from)) {
+                    san.push(file_to_char(file_of(from)));
+                } else if others.iter().all(|&sq| rank_of(sq) != rank_of(from)) {
+                    san.push(rank_to_char(rank_of(from)));
+                } else {
+                    san.push_str(&square_to_str(from));
+                }
+            }
+            if is_capture {
+                san.push('x');
+            }
+            san.push_str(&square_to_str(to));
+        }
+
+        let mut new_pos = pos.clone();
+        if pos.gives_check(self, ci) && new_pos.make_move(self, ci) {
+            san.push(if new_pos.gen_legals(ci).is_empty() {
+                '#'
+            } else {
+                '+'
+            });
+        }
+        san
+    }
+
+    // Accepts SAN with missing or superfluous annotations, castling written with zeros,
+    // promotions without '=' and coordinate notation
+    pub fn from_san(pos: &Position, ci: &CastleInfo, s: &str) -> Option<Move> {
+        let moves = pos.gen_legals(ci).collect::<Vec<Move>>();
+        let s = s.trim().trim_end_matches(|c| "+#!?".contains(c));
+
+        if let Some(&mv) = moves.iter().find(|m| m.to_str(ci) == s) {
+            return Some(mv);
+        }
+
+        match s {
+            "O-O" | "0-0" | "O-O-O" | "0-0-0" => {
+                let queenside = s.len() == 5;
+                return moves
+                    .into_iter()
+                    .find(|m| m.move_type() == CASTLING && (m.to() < m.from()) == queenside);
+            }
+            _ => {}
+        }
+
+        let mut chars = s
+            .chars()
+            .filter(|&c| c != 'x' && c != '-' && c != '=')
+            .collect::<Vec<char>>();
+
+        let pt = match chars.first() {
+            Some(&c) if "NBRQK".contains(c) => {
+                chars.remove(0);
+                char_to_piecetype(c.to_ascii_lowercase())
+            }
+            _ => PAWN,
+        };
+
+        let promo = match chars.last() {
+            Some(&c) if "NBRQKnbrqk".contains(c) && pt == PAWN => {
+                chars.pop();
+                Some(char_to_piecetype(c.to_ascii_lowercase()))
+            }
+            _ => None,
+        };
+
+        if chars.len() < 2 || chars.len() > 4 {
+            return None;
+        }
+        let (to_file, to_rank) = (chars[chars.len() - 2], chars[chars.len() - 1]);
+        if !('a'..='h').contains(&to_file) || !('1'..='8').contains(&to_rank) {
+            return None;
+        }
+        let to = to_square(char_to_rank(to_rank), char_to_file(to_file));
+
+        let (mut from_file, mut from_rank) = (None, None);
+        for &c in chars[..chars.len() - 2].iter() {
+            match c {
+                'a'..='h' => from_file = Some(char_to_file(c)),
+                '1'..='8' => from_rank = Some(char_to_rank(c)),
+                _ => return None,
+            }
+        }
+
+        let mut candidates = moves.into_iter().filter(|m| {
+            m.move_type() != CASTLING
+                && m.to() == to
+                && piecetype_of(pos.piece_on(m.from()).unwrap()) == pt
+                && from_file.is_none_or(|f| file_of(m.from()) == f)
+                && from_rank.is_none_or(|r| rank_of(m.from()) == r)
+                && (m.move_type() == PROMOTION) == promo.is_some()
+                && promo.is_none_or(|p| m.promo_type() == p)
+        });
+
+        match (candidates.next(), candidates.next()) {
+            (Some(mv), None) => Some(mv),
+            _ => None,
+        }
+    }
 }
 
 pub type ScoredMove = (Move, ());
@@ -154,8 +306,10 @@ impl MoveList {
 
 impl Default for MoveList {
     fn default() -> MoveList {
-        let moves = unsafe { std::mem::MaybeUninit::uninit().assume_init() }; //UB
-        MoveList { moves, size: 0 }
+        MoveList {
+            moves: [(NO_MOVE, ()); 256],
+            size: 0,
+        }
     }
 }
 
@@ -166,3 +320,126 @@ impl Iterator for MoveList {
         self.pop().map(|x| x.0)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn castling_encodings() {
+        let (pos, ci) = Position::from_fen("4k3/8/8/8/8/8/8/1R2K1R1 w GB - 0 1").unwrap();
+        let queenside = Move::from_str(&pos, &ci, "e1b1").unwrap();
+        assert_eq!(queenside.move_type(), CASTLING);
+        assert!(Move::from_str(&pos, &ci, "e1c1") == Some(queenside));
+        let kingside = Move::from_str(&pos, &ci, "e1g1").unwrap();
+        assert_eq!(kingside.move_type(), CASTLING);
+        assert_eq!(kingside.to_uci(false), "e1g1");
+
+        let (pos, ci) = Position::from_fen("r3k2r/8/8/8/8/8/8/R3K2R b KQkq - 0 1").unwrap();
+        assert_eq!(
+            Move::from_str(&pos, &ci, "e8c8").unwrap().to_str(&ci),
+            "e8c8"
+        );
+        assert!(Move::from_str(&pos, &ci, "e8a8") == Move::from_str(&pos, &ci, "e8c8"));
+    }
+
+    #[test]
+    fn san() {
+        // FEN, move, SAN written for it and other accepted spellings
+        let cases: [(&str, &str, &str, &[&str]); 14] = [
+            (
+                "4k3/8/8/8/8/4K3/8/R6R w - - 0 1",
+                "a1d1",
+                "Rad1",
+                &["Ra1d1", "Raxd1"],
+            ),
+            (
+                "4k3/8/8/8/8/4K3/8/R6R w - - 0 1",
+                "h1f1",
+                "Rhf1",
+                &["Rh-f1"],
+            ),
+            (
+                "4k3/8/8/R7/8/8/8/R3K3 w - - 0 1",
+                "a1a3",
+                "R1a3",
+                &["Ra1a3"],
+            ),
+            (
+                "4k3/8/8/8/8/Q7/8/Q1Q1K3 w - - 0 1",
+                "a1b2",
+                "Qa1b2",
+                &["Qa1xb2"],
+            ),
+            (
+                "4k3/8/8/3p4/4P3/8/8/4K3 w - - 0 1",
+                "e4d5",
+                "exd5",
+                &["ed5", "e4d5"],
+            ),
+            (
+                "r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1",
+                "e1g1",
+                "O-O",
+                &["0-0", "O-O+"],
+            ),
+            (
+                "r3k2r/8/8/8/8/8/8/R3K2R b KQkq - 0 1",
+                "e8c8",
+                "O-O-O",
+                &["0-0-0"],
+            ),
+            (
+                "4k3/8/8/8/8/8/8/1R2K1R1 w GB - 0 1",
+                "e1g1",
+                "O-O",
+                &["0-0"],
+            ),
+            (
+                "4k3/8/8/8/8/8/8/1R2K1R1 w GB - 0 1",
+                "e1b1",
+                "O-O-O",
+                &["0-0-0"],
+            ),
+            (
+                "8/4P3/8/8/8/8/k7/4K3 w - - 0 1",
+                "e7e8q",
+                "e8=Q",
+                &["e8Q", "e8q", "e7e8q"],
+            ),
+            ("8/4P3/8/8/8/8/k7/4K3 w - - 0 1", "e7e8n", "e8=N", &["e8N"]),
+            (
+                "4k3/8/8/8/8/8/8/R3K3 w - - 0 1",
+                "a1a8",
+                "Ra8+",
+                &["Ra8", "Ra8#"],
+            ),
+            (
+                "6k1/5ppp/8/8/8/8/8/R5K1 w - - 0 1",
+                "a1a8",
+                "Ra8#",
+                &["Ra8", "Ra8+", "Ra8#!"],
+            ),
+            (
+                "4k3/8/8/8/8/8/8/1N3NK1 w - - 0 1",
+                "b1d2",
+                "Nbd2",
+                &["Nb1d2", "b1d2"],
+            ),
+        ];
+        for &(fen, uci, san, spellings) in cases.iter() {
+            let (pos, ci) = Position::from_fen(fen).unwrap();
+            let mv = Move::from_str(&pos, &ci, uci).unwrap();
+            assert_eq!(mv.to_san(&pos, &ci), san, "{}", fen);
+            for &s in spellings.iter().chain([san].iter()) {
+                assert!(Move::from_san(&pos, &ci, s) == Some(mv), "{} {}", fen, s);
+            }
+        }
+
+        // Ambiguous or impossible moves
+        let (pos, ci) = Position::from_fen("4k3/8/8/8/8/8/8/1N3NK1 w - - 0 1").unwrap();
+        for s in ["Nd2", "Nc2", "Nz9", "O-O", "e4", ""].iter() {
+            assert!(Move::from_san(&pos, &ci, s).is_none(), "{}", s);
+        }
+    }
+}
@@ -1,18 +1,26 @@
 use crate::position::{CastleInfo, Position};
+use crate::variant::Variant;
 
 use std::time::Instant;
 
 const KIWIPETE: &str = "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1";
 
-pub fn perft(line: String) {
+pub fn perft(line: String, variant: Variant) {
     let mut tokens = line.split_whitespace();
     let depth: usize = tokens.nth(1).unwrap_or("5").parse().unwrap();
     let mut fen: &str = &tokens.collect::<Vec<&str>>().join(" ");
     if fen.is_empty() {
-        fen = KIWIPETE
+        fen = if variant == Variant::Standard {
+            KIWIPETE
+        } else {
+            variant.start_fen()
+        };
     };
 
-    let (pos, ci) = Position::parse_fen(&fen);
+    let (pos, ci) = match Position::from_variant_fen(fen, variant) {
+        Ok(x) => x,
+        Err(err) => return println!("Invalid FEN: {}", err),
+    };
 
     let start = Instant::now();
     let count = _perft(&pos, &ci, depth);
@@ -24,16 +32,85 @@ pub fn perft(line: String) {
     println!("Time {:.3} ({:.0} nps)\n", time, nps);
 }
 
+// Leaves are counted in bulk from the legal move list of their parent
 pub fn _perft(pos: &Position, ci: &CastleInfo, depth: usize) -> u64 {
     if depth == 0 {
         return 1;
     }
+    if pos.is_variant_loss() {
+        return 0;
+    }
+    let moves = pos.gen_legals(ci);
+    if depth == 1 {
+        return moves.len() as u64;
+    }
     let mut res = 0;
-    for mv in pos.gen_pseudo_legals(ci) {
+    for mv in moves {
         let mut new_pos = pos.clone();
-        if new_pos.make_move(mv, ci) {
-            res += _perft(&new_pos, ci, depth - 1);
-        }
+        let legal = new_pos.make_move(mv, ci);
+        debug_assert!(legal);
+        res += _perft(&new_pos, ci, depth - 1);
     }
     res
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::r#move::Move;
+    use crate::types::A1;
+
+    fn check_perft(variant: Variant, counts: &[u64]) {
+        check_fen_perft(variant.start_fen(), variant, counts);
+    }
+
+    fn check_fen_perft(fen: &str, variant: Variant, counts: &[u64]) {
+        let (pos, ci) = Position::from_variant_fen(fen, variant).unwrap();
+        for (depth, &count) in counts.iter().enumerate() {
+            assert_eq!(
+                _perft(&pos, &ci, depth + 1),
+                count,
+                "{:?} depth {}",
+                variant,
+                depth + 1
+            );
+        }
+    }
+
+    #[test]
+    fn variant_perft() {
+        // No king reaches the center and three checks can't be given within these depths
+        check_perft(Variant::KingOfTheHill, &[20, 400, 8902, 197281, 4865609]);
+        check_perft(Variant::ThreeCheck, &[20, 400, 8902, 197281]);
+        check_perft(Variant::Horde, &[8, 128, 1274, 23310]);
+        check_perft(Variant::Antichess, &[20, 400, 8067, 153299]);
+    }
+
+    #[test]
+    fn variant_rules() {
+        // A king stepping onto d4 ends the game, black has no replies after Kd4
+        let koth = "k7/8/8/8/8/2K5/8/8 w - - 0 1";
+        check_fen_perft(koth, Variant::Standard, &[8, 24]);
+        check_fen_perft(koth, Variant::KingOfTheHill, &[8, 21]);
+        let (mut pos, ci) = Position::from_variant_fen(koth, Variant::KingOfTheHill).unwrap();
+        pos.make_move(Move::from_str(&pos, &ci, "c3d4").unwrap(), &ci);
+        assert!(pos.is_variant_loss());
+
+        // White still needs two checks with 2+3, the last one with 1+3, so Rh8+ ends the game
+        let three_check = |checks| format!("4k3/8/8/8/8/8/8/4K2R w - - {} 0 1", checks);
+        check_fen_perft(&three_check("2+3"), Variant::ThreeCheck, &[14, 63]);
+        check_fen_perft(&three_check("1+3"), Variant::ThreeCheck, &[14, 60]);
+        let (mut pos, ci) =
+            Position::from_variant_fen(&three_check("1+3"), Variant::ThreeCheck).unwrap();
+        pos.make_move(Move::from_str(&pos, &ci, "h1h8").unwrap(), &ci);
+        assert!(pos.is_variant_loss());
+
+        // Double pushes from the first rank don't allow en passant captures by the b3 pawn
+        let horde = "4k3/8/8/8/8/1p6/8/PPPPPPPP w - - 0 1";
+        check_fen_perft(horde, Variant::Horde, &[15, 91]);
+        let (mut pos, ci) = Position::from_variant_fen(horde, Variant::Horde).unwrap();
+        pos.make_move(Move::from_str(&pos, &ci, "a1a3").unwrap(), &ci);
+        assert_eq!(pos.ep, A1);
+        assert!(pos.to_fen(&ci).contains(" b - - "));
+    }
+}
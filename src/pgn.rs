@@ -0,0 +1,487 @@
+use crate::position::{CastleInfo, FenError, Position};
+use crate::r#move::*;
+use crate::types::*;
+
+use std::fmt;
+use std::io::BufRead;
+
+/*
+Games are stored as a tree of positions. Node 0 is the starting position, the first child of a
+node continues the main line and further children are variations. Comments and NAGs belong to
+the node reached by the move they follow, a comment in front of the first move of a line is kept
+as that move's pre_comment.
+*/
+
+const LINE_WIDTH: usize = 80;
+
+const STANDARD_TAGS: [(&str, &str); 7] = [
+    ("Event", "?"),
+    ("Site", "?"),
+    ("Date", "????.??.??"),
+    ("Round", "?"),
+    ("White", "?"),
+    ("Black", "?"),
+    ("Result", "*"),
+];
+
+// Move suffix annotations and their NAG equivalents
+const SUFFIXES: [(&str, u8); 6] = [
+    ("!", 1),
+    ("?", 2),
+    ("!!", 3),
+    ("??", 4),
+    ("!?", 5),
+    ("?!", 6),
+];
+
+#[derive(Debug)]
+pub enum PgnError {
+    InvalidFen(FenError),
+    InvalidTag(String),
+    InvalidNag(String),
+    IllegalMove(String),
+    UnbalancedVariation,
+    Io(std::io::Error),
+}
+
+impl fmt::Display for PgnError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PgnError::InvalidFen(err) => write!(f, "invalid FEN tag: {}", err),
+            PgnError::InvalidTag(s) => write!(f, "invalid tag '{}'", s),
+            PgnError::InvalidNag(s) => write!(f, "invalid NAG '{}'", s),
+            PgnError::IllegalMove(s) => write!(f, "illegal move '{}'", s),
+            PgnError::UnbalancedVariation => write!(f, "unbalanced variation"),
+            PgnError::Io(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct GameNode {
+    pub pos: Position,
+    pub mv: Move,
+    pub parent: Option<usize>,
+    pub children: Vec<usize>,
+    pub nags: Vec<u8>,
+    pub comment: String,
+    pub pre_comment: String,
+}
+
+#[derive(Clone)]
+pub struct Game {
+    pub tags: Vec<(String, String)>,
+    pub ci: CastleInfo,
+    pub nodes: Vec<GameNode>,
+    pub result: String,
+}
+
+impl Game {
+    // Starts a game with the seven tag roster and the tags needed to restore the position
+    pub fn new(pos: Position, ci: CastleInfo) -> Game {
+        let mut tags = STANDARD_TAGS
+            .iter()
+            .map(|&(name, value)| (name.to_owned(), value.to_owned()))
+            .collect::<Vec<(String, String)>>();
+        if ci.frc {
+            tags.push(("Variant".to_owned(), "Chess960".to_owned()));
+        }
+        let fen = pos.to_fen(&ci);
+        if fen != Position::startpos().0.to_fen(&ci) {
+            tags.push(("SetUp".to_owned(), "1".to_owned()));
+            tags.push(("FEN".to_owned(), fen));
+        }
+        Game::with_tags(tags, pos, ci)
+    }
+
+    fn with_tags(tags: Vec<(String, String)>, pos: Position, ci: CastleInfo) -> Game {
+        let root = GameNode {
+            pos,
+            mv: NO_MOVE,
+            parent: None,
+            children: Vec::new(),
+            nags: Vec::new(),
+            comment: String::new(),
+            pre_comment: String::new(),
+        };
+        Game {
+            tags,
+            ci,
+            nodes: vec![root],
+            result: "*".to_owned(),
+        }
+    }
+
+    pub fn tag(&self, name: &str) -> Option<&str> {
+        self.tags.iter().find(|x| x.0 == name).map(|x| x.1.as_str())
+    }
+
+    pub fn set_tag(&mut self, name: &str, value: &str) {
+        match self.tags.iter_mut().find(|x| x.0 == name) {
+            Some(tag) => tag.1 = value.to_owned(),
+            None => self.tags.push((name.to_owned(), value.to_owned())),
+        }
+    }
+
+    pub fn set_result(&mut self, result: &str) {
+        self.result = result.to_owned();
+        self.set_tag("Result", result);
+    }
+
+    // Adds a legal move as the last child of the given node and returns the new node
+    pub fn add_move(&mut self, parent: usize, mv: Move) -> Option<usize> {
+        let mut pos = self.nodes[parent].pos.clone();
+        if !mv.is_pseudolegal(&pos, &self.ci) || !pos.make_move(mv, &self.ci) {
+            return None;
+        }
+        self.nodes.push(GameNode {
+            pos,
+            mv,
+            parent: Some(parent),
+            children: Vec::new(),
+            nags: Vec::new(),
+            comment: String::new(),
+            pre_comment: String::new(),
+        });
+        let idx = self.nodes.len() - 1;
+        self.nodes[parent].children.push(idx);
+        Some(idx)
+    }
+
+    // Node indices of the main line, starting with the root
+    pub fn mainline(&self) -> Vec<usize> {
+        let mut line = vec![0];
+        while let Some(&next) = self.nodes[*line.last().unwrap()].children.first() {
+            line.push(next);
+        }
+        line
+    }
+
+    pub fn from_pgn(pgn: &str) -> Result<Game, PgnError> {
+        let mut tags = Vec::new();
+        let mut lines = pgn.lines().peekable();
+        while let Some(line) = lines.next_if(|x| x.trim().is_empty() || x.trim().starts_with('[')) {
+            let line = line.trim();
+            if !line.is_empty() {
+                tags.push(parse_tag(line)?);
+            }
+        }
+
+        let frc = tags.iter().any(|(name, value)| {
+            name == "Variant"
+                && ["chess960", "fischerandom"].contains(&value.to_lowercase().as_str())
+        });
+        let (pos, mut ci) = match tags.iter().find(|x| x.0 == "FEN") {
+            Some((_, fen)) => Position::from_fen(fen).map_err(PgnError::InvalidFen)?,
+            None => Position::startpos(),
+        };
+        ci.frc |= frc;
+
+        let mut game = Game::with_tags(tags, pos, ci);
+        game.result = game.tag("Result").unwrap_or("*").to_owned();
+        let movetext = lines.collect::<Vec<&str>>().join("\n");
+        game.parse_movetext(&movetext)?;
+        Ok(game)
+    }
+
+    fn parse_movetext(&mut self, text: &str) -> Result<(), PgnError> {
+        let mut chars = text.chars().peekable();
+        let mut current = 0;
+        let mut stack = Vec::new();
+        let mut pre_comment = String::new();
+        let mut line_start = false;
+        let mut new_line = true;
+
+        while let Some(c) = chars.next() {
+            // A '%' only escapes the rest of the line as the first character of the line
+            let first_on_line = std::mem::replace(&mut new_line, c == '\n');
+            match c {
+                '{' => {
+                    let comment = chars.by_ref().take_while(|&c| c != '}').collect::<String>();
+                    let comment = comment.split_whitespace().collect::<Vec<&str>>().join(" ");
+                    let target = if line_start {
+                        &mut pre_comment
+                    } else {
+                        &mut self.nodes[current].comment
+                    };
+                    if !target.is_empty() {
+                        target.push(' ');
+                    }
+                    target.push_str(&comment);
+                }
+                ';' => {
+                    chars.by_ref().take_while(|&c| c != '\n').for_each(drop);
+                    new_line = true;
+                }
+                '%' if first_on_line => {
+                    chars.by_ref().take_while(|&c| c != '\n').for_each(drop);
+                    new_line = true;
+                }
+                '(' => {
+                    // A variation replaces the last move
+                    let parent = self.nodes[current]
+                        .parent
+                        .ok_or(PgnError::UnbalancedVariation)?;
+                    stack.push(current);
+                    current = parent;
+                    stack.push(parent);
+                    line_start = true;
+                }
+                ')' => {
+                    stack.pop().ok_or(PgnError::UnbalancedVariation)?;
+                    current = stack.pop().ok_or(PgnError::UnbalancedVariation)?;
+                    line_start = false;
+                }
+                '$' => {
+                    let nag = take_token(&mut chars, String::new());
+                    let nag = nag.parse().map_err(|_| PgnError::InvalidNag(nag))?;
+                    self.nodes[current].nags.push(nag);
+                }
+                c if c.is_whitespace() || c == '.' => {}
+                c => {
+                    let token = take_token(&mut chars, c.to_string());
+                    if ["1-0", "0-1", "1/2-1/2", "*"].contains(&token.as_str()) {
+                        if stack.is_empty() {
+                            self.result = token;
+                        }
+                        continue;
+                    }
+                    // Move numbers, possibly without a space before the move
+                    let token = token.rsplit('.').next().unwrap().to_owned();
+                    if token.is_empty() {
+                        continue;
+                    }
+
+                    let san = token.trim_end_matches(['!', '?']);
+                    let suffix = &token[san.len()..];
+                    let pos = &self.nodes[current].pos;
+                    let mv = Move::from_san(pos, &self.ci, san)
+                        .ok_or_else(|| PgnError::IllegalMove(token.clone()))?;
+                    current = self.add_move(current, mv).unwrap();
+                    if let Some(&(_, nag)) = SUFFIXES.iter().find(|x| x.0 == suffix) {
+                        self.nodes[current].nags.push(nag);
+                    }
+                    self.nodes[current].pre_comment = std::mem::take(&mut pre_comment);
+                    line_start = false;
+                }
+            }
+        }
+
+        if !stack.is_empty() {
+            return Err(PgnError::UnbalancedVariation);
+        }
+        Ok(())
+    }
+
+    pub fn to_pgn(&self) -> String {
+        let mut pgn = String::new();
+        for (name, value) in self.tags.iter() {
+            let value = value.replace('\\', "\\\\").replace('"', "\\\"");
+            pgn.push_str(&format!("[{} \"{}\"]\n", name, value));
+        }
+        pgn.push('\n');
+
+        let mut tokens = Vec::new();
+        if !self.nodes[0].comment.is_empty() {
+            tokens.push(format!("{{{}}}", self.nodes[0].comment));
+        }
+        self.write_line(0, &mut tokens);
+        tokens.push(self.result.clone());
+
+        // Wrap the movetext
+        let mut line = String::new();
+        for token in tokens {
+            if !line.is_empty() && line.len() + 1 + token.len() > LINE_WIDTH {
+                pgn.push_str(&line);
+                pgn.push('\n');
+                line.clear();
+            }
+            if !line.is_empty() && !line.ends_with('(') && token != ")" {
+                line.push(' ');
+            }
+            line.push_str(&token);
+        }
+        pgn.push_str(&line);
+        pgn.push_str("\n\n");
+        pgn
+    }
+
+    fn write_move(&self, idx: usize, force_number: bool, tokens: &mut Vec<String>) {
+        let node = &self.nodes[idx];
+        let parent = &self.nodes[node.parent.unwrap()].pos;
+        if !node.pre_comment.is_empty() {
+            tokens.push(format!("{{{}}}", node.pre_comment));
+        }
+        if parent.ctm == WHITE {
+            tokens.push(format!("{}.", parent.fullmove));
+        } else if force_number || !node.pre_comment.is_empty() {
+            tokens.push(format!("{}...", parent.fullmove));
+        }
+        tokens.push(node.mv.to_san(parent, &self.ci));
+        tokens.extend(node.nags.iter().map(|nag| format!("${}", nag)));
+        if !node.comment.is_empty() {
+            tokens.push(format!("{{{}}}", node.comment));
+        }
+    }
+
+    fn write_line(&self, mut idx: usize, tokens: &mut Vec<String>) {
+        let mut force_number = true;
+        while let Some((&main, variations)) = self.nodes[idx].children.split_first() {
+            self.write_move(main, force_number, tokens);
+            force_number = !self.nodes[main].comment.is_empty();
+            for &var in variations {
+                tokens.push("(".to_owned());
+                self.write_move(var, true, tokens);
+                self.write_line(var, tokens);
+                tokens.push(")".to_owned());
+                force_number = true;
+            }
+            idx = main;
+        }
+    }
+}
+
+fn take_token(chars: &mut std::iter::Peekable<std::str::Chars>, mut token: String) -> String {
+    while let Some(&c) = chars.peek() {
+        if c.is_whitespace() || "{}();$".contains(c) {
+            break;
+        }
+        token.push(c);
+        chars.next();
+    }
+    token
+}
+
+fn parse_tag(line: &str) -> Result<(String, String), PgnError> {
+    let invalid = || PgnError::InvalidTag(line.to_owned());
+    let inner = line
+        .strip_prefix('[')
+        .and_then(|x| x.strip_suffix(']'))
+        .ok_or_else(invalid)?;
+    let (name, value) = inner
+        .trim()
+        .split_once(char::is_whitespace)
+        .ok_or_else(invalid)?;
+    let value = value
+        .trim()
+        .strip_prefix('"')
+        .and_then(|x| x.strip_suffix('"'))
+        .ok_or_else(invalid)?;
+    Ok((
+        name.to_owned(),
+        value.replace("\\\"", "\"").replace("\\\\", "\\"),
+    ))
+}
+
+// Reads the games of a PGN file one by one
+pub struct PgnReader<R: BufRead> {
+    reader: R,
+    buffer: String,
+}
+
+impl<R: BufRead> PgnReader<R> {
+    pub fn new(reader: R) -> PgnReader<R> {
+        PgnReader {
+            reader,
+            buffer: String::new(),
+        }
+    }
+}
+
+impl<R: BufRead> Iterator for PgnReader<R> {
+    type Item = Result<Game, PgnError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut text = std::mem::take(&mut self.buffer);
+        let mut in_movetext = false;
+        let mut line = String::new();
+        loop {
+            line.clear();
+            match self.reader.read_line(&mut line) {
+                Ok(0) => break,
+                Ok(_) => {}
+                Err(err) => return Some(Err(PgnError::Io(err))),
+            }
+            let trimmed = line.trim();
+            if trimmed.starts_with('[') && in_movetext {
+                // The tags of the next game
+                self.buffer = line.clone();
+                break;
+            }
+            in_movetext |= !trimmed.is_empty() && !trimmed.starts_with('[');
+            text.push_str(&line);
+        }
+
+        if text.trim().is_empty() {
+            return None;
+        }
+        Some(Game::from_pgn(&text))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn percent_escape() {
+        let game = Game::from_pgn("1. e4 e5\n% escaped 2. Qh5\n2. Nf3 Nc6 *").unwrap();
+        assert_eq!(game.mainline().len(), 5);
+        assert!(Game::from_pgn("1. e4 % e5 *").is_err());
+    }
+
+    // Compares the variation trees below two nodes
+    fn assert_same_tree(a: &Game, b: &Game, x: usize, y: usize) {
+        let (na, nb) = (&a.nodes[x], &b.nodes[y]);
+        assert!(na.mv == nb.mv);
+        assert_eq!(na.nags, nb.nags);
+        assert_eq!(na.comment, nb.comment);
+        assert_eq!(na.pre_comment, nb.pre_comment);
+        assert_eq!(na.children.len(), nb.children.len());
+        for (&cx, &cy) in na.children.iter().zip(nb.children.iter()) {
+            assert_same_tree(a, b, cx, cy);
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let pgn = r#"[Event "Round trip"]
+[Site "?"]
+[Date "2024.01.01"]
+[Round "1"]
+[White "A \"quoted\" name"]
+[Black "B"]
+[Result "1-0"]
+[Variant "Chess960"]
+[SetUp "1"]
+[FEN "nrbbqknr/pppppppp/8/8/8/8/PPPPPPPP/NRBBQKNR w HBhb - 0 1"]
+
+{Start} 1. Nf3 {development} (1. g3 $1 g6 (1... Nf6 2. Nf3 {transposes}) 2. Nh3 $6)
+1... Nf6 $2 2. g3 g6 {before castling} 3. O-O! O-O?? ({Instead} 3... Ng4 {attacks}) 4. d4 1-0
+"#;
+        let game = Game::from_pgn(pgn).unwrap();
+        assert!(game.ci.frc);
+        assert_eq!(game.result, "1-0");
+        assert_eq!(game.tag("White"), Some("A \"quoted\" name"));
+        assert_eq!(game.nodes[0].comment, "Start");
+        let mainline = game.mainline();
+        assert_eq!(mainline.len(), 8);
+        assert_eq!(game.nodes[mainline[5]].nags, vec![1]);
+        assert_eq!(game.nodes[mainline[6]].nags, vec![4]);
+        let variation = game.nodes[mainline[5]].children[1];
+        assert_eq!(game.nodes[variation].pre_comment, "Instead");
+        assert_eq!(game.nodes[variation].comment, "attacks");
+        // Both sides castled kingside, king and rook on g and f
+        let last = &game.nodes[mainline[6]].pos;
+        assert_eq!(last.piece_on(G8), Some(B_KING));
+        assert_eq!(last.piece_on(F8), Some(make_piece(BLACK, ROOK)));
+
+        let written = game.to_pgn();
+        let reparsed = Game::from_pgn(&written).unwrap();
+        assert_eq!(reparsed.tags, game.tags);
+        assert_eq!(reparsed.result, game.result);
+        assert!(reparsed.ci.frc);
+        assert_same_tree(&game, &reparsed, 0, 0);
+        assert_eq!(reparsed.to_pgn(), written);
+    }
+}
@@ -1,9 +1,12 @@
 use crate::attacks::*;
 use crate::bitboard::*;
+use crate::book::{POLYGLOT_CASTLE, POLYGLOT_EP, POLYGLOT_KEYS, POLYGLOT_TURN};
+use crate::endgame::MaterialKey;
 use crate::eval::PSQT;
 use crate::r#move::*;
 use crate::transposition::hash;
 use crate::types::*;
+use crate::variant::{Variant, CHECKS_TO_WIN};
 
 use std::fmt;
 
@@ -26,6 +29,58 @@ impl Default for CastleInfo {
     }
 }
 
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum FenError {
+    MissingField(&'static str),
+    RankCount(usize),
+    RankLength(Rank),
+    InvalidPiece(char),
+    KingCount(Color),
+    PawnOnBackRank(Square),
+    InvalidColor(String),
+    OpponentInCheck,
+    InvalidCastling(char),
+    InvalidEnPassant(String),
+    InvalidCounter(String),
+    InvalidChecks(String),
+}
+
+impl fmt::Display for FenError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let color = |c: &Color| ["white", "black"][*c as usize];
+        match self {
+            FenError::MissingField(field) => write!(f, "missing {}", field),
+            FenError::RankCount(n) => write!(f, "expected 8 ranks, found {}", n),
+            FenError::RankLength(rank) => {
+                write!(f, "rank {} does not have 8 squares", rank_to_char(*rank))
+            }
+            FenError::InvalidPiece(c) => write!(f, "invalid piece '{}'", c),
+            FenError::KingCount(c) => write!(f, "{} has a wrong number of kings", color(c)),
+            FenError::PawnOnBackRank(sq) => write!(f, "pawn on back rank {}", square_to_str(*sq)),
+            FenError::InvalidColor(s) => write!(f, "invalid side to move '{}'", s),
+            FenError::OpponentInCheck => write!(f, "side not to move is in check"),
+            FenError::InvalidCastling(c) => {
+                write!(f, "castling right '{}' does not fit the position", c)
+            }
+            FenError::InvalidEnPassant(s) => write!(f, "invalid en passant square '{}'", s),
+            FenError::InvalidCounter(s) => write!(f, "invalid move counter '{}'", s),
+            FenError::InvalidChecks(s) => write!(f, "invalid remaining checks '{}'", s),
+        }
+    }
+}
+
+// State make_move can't recover, saved by make_move_undo for unmake_move
+#[derive(Clone, Copy)]
+pub struct Undo {
+    captured: Option<Piece>,
+    ep: Square,
+    cr: CastleRights,
+    mr50: u16,
+    checks: [u8; 2],
+    hash: u64,
+    piece_eval: i32,
+}
+
 #[derive(Clone)]
 pub struct Position {
     piece_bb: [BitBoard; 7],
@@ -34,9 +89,12 @@ pub struct Position {
 
     pub ctm: Color,
     pub ep: Square,
-    pub mr50: u8,
+    pub mr50: u16,
     pub cr: CastleRights,
-    pub fullmove: u8,
+    pub fullmove: u16,
+
+    pub variant: Variant,
+    pub checks: [u8; 2],
 
     pub hash: u64,
     pub piece_eval: i32,
@@ -56,18 +114,13 @@ impl Position {
         let moving_piece: Piece = self.piece_on(from).unwrap(); // We have to initialize this here due to the fact that a friendly rook might temporarily move on top of our king on a FRC castle
 
         if mv.move_type() == CASTLING {
-            if self.in_check(self.ctm) {
+            if !self.castling_allowed(mv, ci) {
                 return false;
             }
             for &cr in [[W_KS, W_QS], [B_KS, B_QS]][self.ctm as usize].iter() {
                 let r_from = ci.castle_rooks[cr as usize];
                 if to == r_from {
                     let k_target = CASTLE_K_TARGET[cr as usize];
-                    for sq in BETWEEN_BB[from as usize][k_target as usize] {
-                        if self.square_attacked(sq, swap_color(self.ctm)) {
-                            return false;
-                        }
-                    }
                     let r_target = CASTLE_R_TARGET[cr as usize];
                     self.move_piece(make_piece(self.ctm, ROOK), r_from, r_target);
                     to = k_target;
@@ -100,7 +153,8 @@ impl Position {
         self.ep = A1;
         if piecetype_of(moving_piece) == PAWN {
             self.mr50 = 0;
-            if to ^ from == 16 {
+            // Horde pawns double pushing from the first rank can't be captured en passant
+            if to ^ from == 16 && rank_of(from) == relative_rank(RANK_2, self.ctm) {
                 self.ep = ep_captured_sq(to);
                 self.hash ^= hash::EP[self.ep as usize];
             }
@@ -110,12 +164,177 @@ impl Position {
         self.cr &= ci.castle_rights[from as usize] & ci.castle_rights[to as usize];
         self.hash ^= hash::CASTLE_RIGHTS[self.cr as usize];
 
-        self.fullmove += self.ctm;
+        if self.variant == Variant::ThreeCheck && self.in_check(swap_color(self.ctm)) {
+            let c = self.ctm as usize;
+            self.hash ^= hash::CHECKS[c][self.checks[c] as usize];
+            self.checks[c] = (self.checks[c] + 1).min(CHECKS_TO_WIN);
+            self.hash ^= hash::CHECKS[c][self.checks[c] as usize];
+        }
+
+        self.fullmove += self.ctm as u16;
         self.ctm = swap_color(self.ctm);
         self.hash ^= hash::CTM;
+        debug_assert_eq!(self.validate(ci), Ok(()));
         true
     }
 
+    // The king may not castle out of or through check, the target square is checked after the move
+    fn castling_allowed(&self, mv: Move, ci: &CastleInfo) -> bool {
+        if self.in_check(self.ctm) {
+            return false;
+        }
+        let cr = self.castling_right(mv, ci);
+        let k_target = CASTLE_K_TARGET[cr as usize];
+        let mut path = BETWEEN_BB[mv.from() as usize][k_target as usize];
+        path.all(|sq| !self.square_attacked(sq, swap_color(self.ctm)))
+    }
+
+    fn castling_right(&self, mv: Move, ci: &CastleInfo) -> CastleRights {
+        [[W_KS, W_QS], [B_KS, B_QS]][self.ctm as usize]
+            .iter()
+            .copied()
+            .find(|&cr| ci.castle_rooks[cr as usize] == mv.to())
+            .unwrap()
+    }
+
+    // Make/unmake alternative to copy-make, the position is unchanged if the move is illegal
+    pub fn make_move_undo(&mut self, mv: Move, ci: &CastleInfo) -> Option<Undo> {
+        if mv.move_type() == CASTLING && !self.castling_allowed(mv, ci) {
+            return None;
+        }
+        let undo = Undo {
+            captured: self.captured_piece(mv),
+            ep: self.ep,
+            cr: self.cr,
+            mr50: self.mr50,
+            checks: self.checks,
+            hash: self.hash,
+            piece_eval: self.piece_eval,
+        };
+        if self.make_move(mv, ci) {
+            Some(undo)
+        } else {
+            // The pieces were moved, but the side to move hasn't changed yet
+            self.unmake_pieces(mv, ci, &undo);
+            None
+        }
+    }
+
+    pub fn unmake_move(&mut self, mv: Move, ci: &CastleInfo, undo: &Undo) {
+        self.ctm = swap_color(self.ctm);
+        self.fullmove -= self.ctm as u16;
+        self.unmake_pieces(mv, ci, undo);
+        debug_assert_eq!(self.validate(ci), Ok(()));
+    }
+
+    // Recomputes the incrementally updated state from the board and compares it
+    pub fn validate(&self, ci: &CastleInfo) -> Result<(), &'static str> {
+        let mut piece_bb = [BB_ZERO; 7];
+        let mut color_bb = [BB_ZERO; 2];
+        let (mut hash, mut piece_eval) = (0, 0);
+        for sq in 0..64 {
+            if let Some(piece) = self.piece_on(sq) {
+                if !(PAWN..=KING).contains(&piecetype_of(piece)) || color_of(piece) > BLACK {
+                    return Err("invalid piece on the board");
+                }
+                piece_bb[piecetype_of(piece) as usize] |= bb!(sq);
+                piece_bb[ALL as usize] |= bb!(sq);
+                color_bb[color_of(piece) as usize] |= bb!(sq);
+                hash ^= hash::PIECES[piece as usize][sq as usize];
+                piece_eval += PSQT[piece as usize][sq as usize];
+            }
+        }
+        if piece_bb != self.piece_bb {
+            return Err("piece bitboards don't match the board");
+        }
+        if color_bb != self.color_bb {
+            return Err("color bitboards don't match the board");
+        }
+        if piece_eval != self.piece_eval {
+            return Err("piece evaluation doesn't match the board");
+        }
+
+        for &c in [WHITE, BLACK].iter() {
+            if !self
+                .variant
+                .king_range(c)
+                .contains(&self.piece_bb(KING, c).popcount())
+            {
+                return Err("a side doesn't have the number of kings of the variant");
+            }
+        }
+        if self.in_check(swap_color(self.ctm)) {
+            return Err("side not to move is in check");
+        }
+
+        for &cr in [W_KS, W_QS, B_KS, B_QS].iter() {
+            if self.cr & cr == 0 {
+                continue;
+            }
+            let color = (cr & B_CASTLING != 0) as Color;
+            if self.piece_on(ci.castle_rooks[cr as usize]) != Some(make_piece(color, ROOK))
+                || ci.castle_rights[self.king_sq(color) as usize] & cr != 0
+            {
+                return Err("castling rights don't match the king and rook squares");
+            }
+        }
+
+        if self.ep != A1
+            && (rank_of(self.ep) != relative_rank(RANK_6, self.ctm)
+                || self.piece_on(ep_captured_sq(self.ep))
+                    != Some(make_piece(swap_color(self.ctm), PAWN)))
+        {
+            return Err("en passant square without a pawn to capture");
+        }
+
+        hash ^= hash::EP[self.ep as usize] ^ hash::CASTLE_RIGHTS[self.cr as usize];
+        if self.ctm == BLACK {
+            hash ^= hash::CTM;
+        }
+        for c in 0..2 {
+            hash ^= hash::CHECKS[c][self.checks[c] as usize];
+        }
+        if hash != self.hash {
+            return Err("hash doesn't match the position");
+        }
+        Ok(())
+    }
+
+    fn unmake_pieces(&mut self, mv: Move, ci: &CastleInfo, undo: &Undo) {
+        let (from, to) = (mv.from(), mv.to());
+        match mv.move_type() {
+            CASTLING => {
+                let cr = self.castling_right(mv, ci);
+                let (k_target, r_target) =
+                    (CASTLE_K_TARGET[cr as usize], CASTLE_R_TARGET[cr as usize]);
+                self.toggle_piece_on_sq(make_piece(self.ctm, KING), k_target);
+                self.toggle_piece_on_sq(make_piece(self.ctm, ROOK), r_target);
+                self.toggle_piece_on_sq(make_piece(self.ctm, KING), from);
+                self.toggle_piece_on_sq(make_piece(self.ctm, ROOK), to);
+            }
+            PROMOTION => {
+                self.toggle_piece_on_sq(make_piece(self.ctm, mv.promo_type()), to);
+                self.toggle_piece_on_sq(make_piece(self.ctm, PAWN), from);
+            }
+            _ => {
+                let piece = self.board[to as usize];
+                self.toggle_piece_on_sq(piece, to);
+                self.toggle_piece_on_sq(piece, from);
+            }
+        }
+        if let Some(piece) = undo.captured {
+            self.toggle_piece_on_sq(piece, mv.capture_to());
+        }
+
+        // Restoring these also reverts the hash and evaluation changes of the toggles above
+        self.ep = undo.ep;
+        self.cr = undo.cr;
+        self.mr50 = undo.mr50;
+        self.checks = undo.checks;
+        self.hash = undo.hash;
+        self.piece_eval = undo.piece_eval;
+    }
+
     fn move_piece(&mut self, piece: Piece, from_sq: Square, to_sq: Square) {
         self.toggle_piece_on_sq(piece, from_sq);
         self.sub_piece_eval(piece, from_sq);
@@ -143,7 +362,7 @@ impl Position {
     }
 
     pub fn in_check(&self, c: Color) -> bool {
-        self.square_attacked(self.king_sq(c), swap_color(c))
+        self.has_royal_king(c) && self.square_attacked(self.king_sq(c), swap_color(c))
     }
 
     pub fn gen_pseudo_legals(&self, ci: &CastleInfo) -> MoveList {
@@ -152,16 +371,141 @@ impl Position {
         mv_list
     }
 
+    // Captures are mandatory in antichess, quiet moves are only generated if there are none
     pub fn _gen_pseudo_legals(&self, ci: &CastleInfo, list: &mut MoveList) {
+        if self.variant == Variant::Antichess {
+            let len = list.len();
+            self._gen_captures(ci, list);
+            if list.len() == len {
+                self._gen_quiets(ci, list);
+            }
+            return;
+        }
+        let targets = !self.color_bb(self.ctm);
+        self._gen_moves(ci, list, targets, targets, true);
+    }
+
+    pub fn gen_captures(&self, ci: &CastleInfo) -> MoveList {
+        let mut mv_list = MoveList::default();
+        self._gen_captures(ci, &mut mv_list);
+        mv_list
+    }
+
+    // Captures, including en passant and capturing promotions
+    pub fn _gen_captures(&self, ci: &CastleInfo, list: &mut MoveList) {
+        let targets = self.color_bb(swap_color(self.ctm));
+        self._gen_moves(ci, list, targets, targets, false);
+    }
+
+    pub fn gen_quiets(&self, ci: &CastleInfo) -> MoveList {
+        let mut mv_list = MoveList::default();
+        self._gen_quiets(ci, &mut mv_list);
+        mv_list
+    }
+
+    // Non-captures, including castling and promotions by a push
+    pub fn _gen_quiets(&self, ci: &CastleInfo, list: &mut MoveList) {
+        let targets = !self.piecetype_bb(ALL);
+        self._gen_moves(ci, list, targets, targets, true);
+    }
+
+    pub fn gen_evasions(&self, ci: &CastleInfo) -> MoveList {
+        let mut mv_list = MoveList::default();
+        self._gen_evasions(ci, &mut mv_list);
+        mv_list
+    }
+
+    // Pseudo-legal moves out of check: king moves, and captures of or blocks against a single checker
+    pub fn _gen_evasions(&self, ci: &CastleInfo, list: &mut MoveList) {
+        let checkers = self.checkers();
+        if checkers.is_empty() {
+            return self._gen_pseudo_legals(ci, list);
+        }
+        let targets = if checkers.popcount() > 1 {
+            BB_ZERO
+        } else {
+            BETWEEN_BB[self.king_sq(self.ctm) as usize][checkers.lsb() as usize] | checkers
+        };
+        self._gen_moves(ci, list, targets, !self.color_bb(self.ctm), false);
+    }
+
+    pub fn gen_checks(&self, ci: &CastleInfo) -> MoveList {
+        let mut mv_list = MoveList::default();
+        self._gen_checks(ci, &mut mv_list);
+        mv_list
+    }
+
+    // Quiet moves giving a direct or discovered check, without castling and promotions
+    pub fn _gen_checks(&self, _ci: &CastleInfo, list: &mut MoveList) {
+        let color = self.ctm;
+        if !self.has_royal_king(swap_color(color)) {
+            return;
+        }
+        let occ = self.piecetype_bb(ALL);
+        let empty = !occ;
+        let enemy_k_sq = self.king_sq(swap_color(color));
+        let discoverers = self.slider_blockers(enemy_k_sq, color) & self.color_bb(color);
+
+        let our_piece = |x| self.piece_bb(x, color);
+        // Moving off the line between a slider and the enemy king gives a discovered check
+        let discovered = |from: Square| {
+            if (discoverers & bb!(from)).not_empty() {
+                !LINE_BB[enemy_k_sq as usize][from as usize]
+            } else {
+                BB_ZERO
+            }
+        };
+
+        for &pt in [KING, KNIGHT, BISHOP, ROOK, QUEEN].iter() {
+            let direct = if pt == KING {
+                BB_ZERO
+            } else {
+                attack_bb(pt, enemy_k_sq, occ)
+            };
+            for from in our_piece(pt) {
+                for to in attack_bb(pt, from, occ) & empty & (direct | discovered(from)) {
+                    list.push(Move::new(from, to, NORMAL, None));
+                }
+            }
+        }
+
+        let pawns = our_piece(PAWN) & !RANK_BB[relative_rank(RANK_7, color)];
+        let push = pawn_push(pawns, color, occ);
+        let double = pawn_push(push & self.double_push_bb(color), color, occ);
+        let direct = pawn_attack_bb(enemy_k_sq, swap_color(color));
+
+        macro_rules! pawn_checks {($ ($dir: expr, $targets: expr); +) => {$(
+            for to in $targets {
+                let from = (to as Direction - relative_dir($dir, color)) as Square;
+                if ((direct | discovered(from)) & bb!(to)).not_empty() {
+                    list.push(Move::new(from, to, NORMAL, None));
+                }
+            }
+        )+};}
+        pawn_checks!(
+            NORTH, push;
+            NORTH+NORTH, double
+        );
+    }
+
+    // Generates the moves to the target squares, king moves are restricted to their own targets
+    fn _gen_moves(
+        &self,
+        ci: &CastleInfo,
+        list: &mut MoveList,
+        targets: BitBoard,
+        king_targets: BitBoard,
+        castling: bool,
+    ) {
         let color = self.ctm;
         let occ = self.piecetype_bb(ALL);
-        let targets = !self.color_bb(color);
         let enemies = self.color_bb(swap_color(color));
 
         let our_piece = |x| self.piece_bb(x, color);
 
         // Non-pawns
         for &pt in [KING, KNIGHT, BISHOP, ROOK, QUEEN].iter() {
+            let targets = if pt == KING { king_targets } else { targets };
             for from in our_piece(pt) {
                 let attacks = attack_bb(pt, from, occ) & targets;
                 for to in attacks {
@@ -175,10 +519,21 @@ impl Position {
         let pawns_not7th = our_piece(PAWN) ^ pawns_on7th;
 
         let push = pawn_push(pawns_not7th, color, occ);
-        let double = pawn_push(push & RANK_BB[relative_rank(RANK_3, color)], color, occ);
+        let double = pawn_push(push & self.double_push_bb(color), color, occ);
         let west_attacks = pawn_bb_west_bb(pawns_not7th, color);
         let east_attacks = pawn_bb_east_bb(pawns_not7th, color);
 
+        // En passant is a capture, so it needs enemy targets. It's generated if it captures a
+        // target or, when evading a slider check, if the en passant square blocks the check.
+        let ep_targets = if self.ep != A1
+            && (targets & enemies).not_empty()
+            && (bb!(self.ep, ep_captured_sq(self.ep)) & targets).not_empty()
+        {
+            bb!(self.ep)
+        } else {
+            BB_ZERO
+        };
+
         macro_rules! pawn_pseudolegals {($ ($dir: expr, $mt: ident, $targets: expr); +) => {$(
             for to in $targets{
                 let from = (to as Direction - relative_dir($dir, color)) as Square;
@@ -186,29 +541,32 @@ impl Position {
             }
         )+};}
         pawn_pseudolegals!(
-            NORTH, NORMAL, push;
-            NORTH+NORTH, NORMAL, double;
-            NORTH_WEST, NORMAL, west_attacks & enemies;
-            NORTH_EAST, NORMAL, east_attacks & enemies;
-            NORTH_WEST, ENPASSANT, west_attacks & bb!(self.ep);
-            NORTH_EAST, ENPASSANT, east_attacks & bb!(self.ep)
+            NORTH, NORMAL, push & targets;
+            NORTH+NORTH, NORMAL, double & targets;
+            NORTH_WEST, NORMAL, west_attacks & enemies & targets;
+            NORTH_EAST, NORMAL, east_attacks & enemies & targets;
+            NORTH_WEST, ENPASSANT, west_attacks & ep_targets;
+            NORTH_EAST, ENPASSANT, east_attacks & ep_targets
         );
 
         macro_rules! pawn_promos {($ ($dir: expr, $targets: expr); +) => {$(
             for to in $targets {
                 let from = (to as Direction - relative_dir($dir, color)) as Square;
-                for &promo in [KNIGHT, BISHOP, ROOK, QUEEN].iter() {
+                for &promo in self.variant.promo_types().iter() {
                     list.push(Move::new(from, to, PROMOTION, Some(promo)))
                 }
             }
         )+};}
         pawn_promos!(
-            NORTH, pawn_push(pawns_on7th, color, occ);
-            NORTH_WEST, pawn_bb_west_bb(pawns_on7th, color) & enemies;
-            NORTH_EAST, pawn_bb_east_bb(pawns_on7th, color) & enemies
+            NORTH, pawn_push(pawns_on7th, color, occ) & targets;
+            NORTH_WEST, pawn_bb_west_bb(pawns_on7th, color) & enemies & targets;
+            NORTH_EAST, pawn_bb_east_bb(pawns_on7th, color) & enemies & targets
         );
 
         // Castling
+        if !castling || self.cr & [W_CASTLING, B_CASTLING][color as usize] == 0 {
+            return;
+        }
         let k_sq = self.king_sq(color);
         for &cr in [[W_KS, W_QS], [B_KS, B_QS]][color as usize].iter() {
             if (self.cr & cr) > 0
@@ -221,6 +579,246 @@ impl Position {
         }
     }
 
+    // Pieces of both colors attacking the square, given the occupancy
+    pub fn attackers_to(&self, sq: Square, occ: BitBoard) -> BitBoard {
+        let bishops = self.bishop_likes_bb(WHITE) | self.bishop_likes_bb(BLACK);
+        let rooks = self.rook_likes_bb(WHITE) | self.rook_likes_bb(BLACK);
+        (attack_bb(KNIGHT, sq, occ) & self.piecetype_bb(KNIGHT))
+            | (attack_bb(KING, sq, occ) & self.piecetype_bb(KING))
+            | (attack_bb(BISHOP, sq, occ) & bishops)
+            | (attack_bb(ROOK, sq, occ) & rooks)
+            | (pawn_attack_bb(sq, BLACK) & self.piece_bb(PAWN, WHITE))
+            | (pawn_attack_bb(sq, WHITE) & self.piece_bb(PAWN, BLACK))
+    }
+
+    // Enemy pieces giving check to the side to move
+    pub fn checkers(&self) -> BitBoard {
+        if !self.has_royal_king(self.ctm) {
+            return BB_ZERO;
+        }
+        let occ = self.piecetype_bb(ALL);
+        self.attackers_to(self.king_sq(self.ctm), occ) & self.color_bb(swap_color(self.ctm))
+    }
+
+    // Pieces of the given color which are the only blocker between their king and an enemy slider
+    pub fn pinned(&self, c: Color) -> BitBoard {
+        if !self.has_royal_king(c) {
+            return BB_ZERO;
+        }
+        self.slider_blockers(self.king_sq(c), swap_color(c)) & self.color_bb(c)
+    }
+
+    // Pieces of any color which are the only blocker between the square and a slider of color c
+    fn slider_blockers(&self, sq: Square, c: Color) -> BitBoard {
+        let snipers = (attack_bb(ROOK, sq, BB_ZERO) & self.rook_likes_bb(c))
+            | (attack_bb(BISHOP, sq, BB_ZERO) & self.bishop_likes_bb(c));
+        let mut blockers = BB_ZERO;
+        for sniper in snipers {
+            let between = BETWEEN_BB[sq as usize][sniper as usize] & self.piecetype_bb(ALL);
+            if between.popcount() == 1 {
+                blockers |= between;
+            }
+        }
+        blockers
+    }
+
+    pub fn moved_piece(&self, mv: Move) -> Piece {
+        self.board[mv.from() as usize]
+    }
+
+    // The castling rook isn't captured, an en passant capture takes the pawn behind the target
+    pub fn captured_piece(&self, mv: Move) -> Option<Piece> {
+        match mv.move_type() {
+            CASTLING => None,
+            _ => self.piece_on(mv.capture_to()),
+        }
+    }
+
+    pub fn is_capture(&self, mv: Move) -> bool {
+        self.captured_piece(mv).is_some()
+    }
+
+    // Neither a capture nor a promotion
+    pub fn is_quiet(&self, mv: Move) -> bool {
+        mv.move_type() != PROMOTION && !self.is_capture(mv)
+    }
+
+    // Whether the pseudo-legal move checks the enemy king, directly or by discovery
+    pub fn gives_check(&self, mv: Move, ci: &CastleInfo) -> bool {
+        let color = self.ctm;
+        if !self.has_royal_king(swap_color(color)) {
+            return false;
+        }
+        let enemy_k_sq = self.king_sq(swap_color(color));
+        let (from, mut to) = (mv.from(), mv.to());
+        let mut pt = piecetype_of(self.moved_piece(mv));
+
+        // Occupancy and sliders after the move
+        let mut occ = self.piecetype_bb(ALL) ^ bb!(from);
+        let mut bishops = self.bishop_likes_bb(color) & !bb!(from);
+        let mut rooks = self.rook_likes_bb(color) & !bb!(from);
+        match mv.move_type() {
+            CASTLING => {
+                let cr = self.castling_right(mv, ci);
+                let r_target = CASTLE_R_TARGET[cr as usize];
+                occ = (occ ^ bb!(to)) | bb!(r_target);
+                rooks = (rooks ^ bb!(to)) | bb!(r_target);
+                to = CASTLE_K_TARGET[cr as usize];
+            }
+            ENPASSANT => occ ^= bb!(ep_captured_sq(to)),
+            PROMOTION => pt = mv.promo_type(),
+            _ => {}
+        }
+        occ |= bb!(to);
+        if pt == BISHOP || pt == QUEEN {
+            bishops |= bb!(to);
+        }
+        if pt == ROOK || pt == QUEEN {
+            rooks |= bb!(to);
+        }
+
+        let direct = match pt {
+            PAWN => pawn_attack_bb(to, color),
+            KNIGHT => attack_bb(KNIGHT, to, BB_ZERO),
+            _ => BB_ZERO,
+        };
+        (direct & bb!(enemy_k_sq)).not_empty()
+            || (attack_bb(BISHOP, enemy_k_sq, occ) & bishops).not_empty()
+            || (attack_bb(ROOK, enemy_k_sq, occ) & rooks).not_empty()
+    }
+
+    pub fn gen_legals(&self, ci: &CastleInfo) -> MoveList {
+        let mut mv_list = MoveList::default();
+        self._gen_legals(ci, &mut mv_list);
+        mv_list
+    }
+
+    pub fn _gen_legals(&self, ci: &CastleInfo, list: &mut MoveList) {
+        let color = self.ctm;
+        // Without a royal king, like white in horde or in antichess, every pseudo-legal move is legal
+        if !self.has_royal_king(color) {
+            return self._gen_pseudo_legals(ci, list);
+        }
+        let them = swap_color(color);
+        let occ = self.piecetype_bb(ALL);
+        let own = self.color_bb(color);
+        let enemies = self.color_bb(them);
+        let k_sq = self.king_sq(color);
+        let checkers = self.checkers();
+
+        let our_piece = |x| self.piece_bb(x, color);
+        let attacked = |sq, occ| (self.attackers_to(sq, occ) & enemies).not_empty();
+
+        // The king may not stay on a line attacked by a slider, so it is removed from the occupancy
+        for to in attack_bb(KING, k_sq, occ) & !own {
+            if !attacked(to, occ ^ bb!(k_sq)) {
+                list.push(Move::new(k_sq, to, NORMAL, None));
+            }
+        }
+        if checkers.popcount() > 1 {
+            return;
+        }
+
+        // When in check, other pieces have to capture the checker or block the check
+        let targets = if checkers.not_empty() {
+            BETWEEN_BB[k_sq as usize][checkers.lsb() as usize] | checkers
+        } else {
+            !own
+        };
+        let pinned = self.pinned(color);
+        let legal = |from: Square, to: Square| {
+            (pinned & bb!(from)).is_empty()
+                || (LINE_BB[k_sq as usize][from as usize] & bb!(to)).not_empty()
+        };
+
+        for &pt in [KNIGHT, BISHOP, ROOK, QUEEN].iter() {
+            for from in our_piece(pt) {
+                for to in attack_bb(pt, from, occ) & targets {
+                    if legal(from, to) {
+                        list.push(Move::new(from, to, NORMAL, None));
+                    }
+                }
+            }
+        }
+
+        // Pawns
+        let pawns_on7th = our_piece(PAWN) & RANK_BB[relative_rank(RANK_7, color)];
+        let pawns_not7th = our_piece(PAWN) ^ pawns_on7th;
+
+        let push = pawn_push(pawns_not7th, color, occ);
+        let double = pawn_push(push & self.double_push_bb(color), color, occ);
+        let west_attacks = pawn_bb_west_bb(pawns_not7th, color);
+        let east_attacks = pawn_bb_east_bb(pawns_not7th, color);
+
+        macro_rules! pawn_legals {($ ($dir: expr, $targets: expr); +) => {$(
+            for to in $targets {
+                let from = (to as Direction - relative_dir($dir, color)) as Square;
+                if legal(from, to) {
+                    list.push(Move::new(from, to, NORMAL, None));
+                }
+            }
+        )+};}
+        pawn_legals!(
+            NORTH, push & targets;
+            NORTH+NORTH, double & targets;
+            NORTH_WEST, west_attacks & enemies & targets;
+            NORTH_EAST, east_attacks & enemies & targets
+        );
+
+        // En passant can uncover a check along the rank of the captured pawn, so verify it directly
+        macro_rules! ep_legals {($ ($dir: expr, $targets: expr); +) => {$(
+            for to in $targets {
+                let from = (to as Direction - relative_dir($dir, color)) as Square;
+                let captured = ep_captured_sq(to);
+                let new_occ = occ ^ bb!(from, captured, to);
+                if (self.attackers_to(k_sq, new_occ) & enemies & !bb!(captured)).is_empty() {
+                    list.push(Move::new(from, to, ENPASSANT, None));
+                }
+            }
+        )+};}
+        if self.ep != A1 {
+            ep_legals!(
+                NORTH_WEST, west_attacks & bb!(self.ep);
+                NORTH_EAST, east_attacks & bb!(self.ep)
+            );
+        }
+
+        macro_rules! pawn_promos {($ ($dir: expr, $targets: expr); +) => {$(
+            for to in $targets {
+                let from = (to as Direction - relative_dir($dir, color)) as Square;
+                if legal(from, to) {
+                    for &promo in [KNIGHT, BISHOP, ROOK, QUEEN].iter() {
+                        list.push(Move::new(from, to, PROMOTION, Some(promo)))
+                    }
+                }
+            }
+        )+};}
+        pawn_promos!(
+            NORTH, pawn_push(pawns_on7th, color, occ) & targets;
+            NORTH_WEST, pawn_bb_west_bb(pawns_on7th, color) & enemies & targets;
+            NORTH_EAST, pawn_bb_east_bb(pawns_on7th, color) & enemies & targets
+        );
+
+        // Castling, in FRC the castling rook may have shielded the king's target square
+        if checkers.is_empty() {
+            for &cr in [[W_KS, W_QS], [B_KS, B_QS]][color as usize].iter() {
+                let r_from = ci.castle_rooks[cr as usize];
+                if (self.cr & cr) == 0
+                    || (ci.castle_path[cr as usize] & occ & !bb!(k_sq, r_from)).not_empty()
+                {
+                    continue;
+                }
+                let (k_target, r_target) =
+                    (CASTLE_K_TARGET[cr as usize], CASTLE_R_TARGET[cr as usize]);
+                let new_occ = (occ ^ bb!(k_sq, r_from)) | bb!(k_target, r_target);
+                let mut path = BETWEEN_BB[k_sq as usize][k_target as usize];
+                if path.all(|sq| !attacked(sq, occ)) && !attacked(k_target, new_occ) {
+                    list.push(Move::new(k_sq, r_from, CASTLING, None));
+                }
+            }
+        }
+    }
+
     pub fn color_bb(&self, c: Color) -> BitBoard {
         self.color_bb[c as usize]
     }
@@ -237,6 +835,16 @@ impl Position {
         (self.piecetype_bb(KING) & self.color_bb(c)).lsb()
     }
 
+    // Pushed pawns which may push once more, horde pawns can double push from the first rank
+    pub fn double_push_bb(&self, c: Color) -> BitBoard {
+        let third = RANK_BB[relative_rank(RANK_3, c)];
+        if self.variant == Variant::Horde {
+            third | RANK_BB[relative_rank(RANK_2, c)]
+        } else {
+            third
+        }
+    }
+
     pub fn bishop_likes_bb(&self, c: Color) -> BitBoard {
         (self.piecetype_bb(BISHOP) | self.piecetype_bb(QUEEN)) & self.color_bb(c)
     }
@@ -245,74 +853,274 @@ impl Position {
         (self.piecetype_bb(ROOK) | self.piecetype_bb(QUEEN)) & self.color_bb(c)
     }
 
-    pub fn parse_fen(fen: &str) -> (Position, CastleInfo) {
-        let mut pos = Position::default();
+    // Polyglot hashes the en passant file only if a pawn can capture en passant
+    pub fn polyglot_key(&self) -> u64 {
+        let mut key = 0;
+        for sq in self.piecetype_bb(ALL) {
+            let piece = self.board[sq as usize];
+            let kind =
+                2 * (piecetype_of(piece) - PAWN) as usize + (color_of(piece) == WHITE) as usize;
+            key ^= POLYGLOT_KEYS[64 * kind + sq as usize];
+        }
+        for (i, &cr) in [W_KS, W_QS, B_KS, B_QS].iter().enumerate() {
+            if self.cr & cr != 0 {
+                key ^= POLYGLOT_KEYS[POLYGLOT_CASTLE + i];
+            }
+        }
+        if self.ep != A1
+            && (pawn_attack_bb(self.ep, swap_color(self.ctm)) & self.piece_bb(PAWN, self.ctm))
+                .not_empty()
+        {
+            key ^= POLYGLOT_KEYS[POLYGLOT_EP + file_of(self.ep)];
+        }
+        if self.ctm == WHITE {
+            key ^= POLYGLOT_KEYS[POLYGLOT_TURN];
+        }
+        key
+    }
+
+    // Neither side can mate: bare kings or a single minor piece. Variants can still be won by
+    // other means
+    pub fn is_insufficient_material(&self) -> bool {
+        let heavies = self.piecetype_bb(PAWN) | self.piecetype_bb(ROOK) | self.piecetype_bb(QUEEN);
+        self.variant == Variant::Standard
+            && heavies.is_empty()
+            && self.piecetype_bb(ALL).popcount() <= 3
+    }
+
+    pub fn material_key(&self) -> MaterialKey {
+        let mut key = 0;
+        for &c in [WHITE, BLACK].iter() {
+            for pt in PAWN..KING {
+                key |= (self.piece_bb(pt, c).popcount() as MaterialKey) << (4 * make_piece(c, pt));
+            }
+        }
+        key
+    }
+
+    pub fn from_fen(fen: &str) -> Result<(Position, CastleInfo), FenError> {
+        Position::from_variant_fen(fen, Variant::Standard)
+    }
+
+    // Three-check FENs carry the remaining checks as "3+3" after the en passant square
+    pub fn from_variant_fen(
+        fen: &str,
+        variant: Variant,
+    ) -> Result<(Position, CastleInfo), FenError> {
+        let mut pos = Position {
+            variant,
+            ..Position::default()
+        };
         let mut cinfo = CastleInfo::default();
         let ci = &mut cinfo;
-        let mut tokens = fen.split_ascii_whitespace();
-
-        let mut sq = A8;
-        for c in tokens.next().unwrap().chars() {
-            match c {
-                '/' => sq -= 16,
-                '1'..='8' => sq += c.to_digit(10).unwrap(),
-                _ => {
-                    pos.add_piece(c, sq);
-                    sq += 1;
+        let mut tokens = fen.split_ascii_whitespace().peekable();
+
+        let placement = tokens
+            .next()
+            .ok_or(FenError::MissingField("piece placement"))?;
+        let ranks = placement.split('/').collect::<Vec<&str>>();
+        if ranks.len() != RANK_NB {
+            return Err(FenError::RankCount(ranks.len()));
+        }
+        for (i, rank_str) in ranks.iter().enumerate() {
+            let rank = RANK_8 - i;
+            let mut file = FILE_A;
+            for c in rank_str.chars() {
+                match c {
+                    '1'..='8' => file += c.to_digit(10).unwrap() as File,
+                    'P' | 'N' | 'B' | 'R' | 'Q' | 'K' | 'p' | 'n' | 'b' | 'r' | 'q' | 'k' => {
+                        if file >= FILE_NB {
+                            return Err(FenError::RankLength(rank));
+                        }
+                        pos.add_piece(c, to_square(rank, file));
+                        file += 1;
+                    }
+                    _ => return Err(FenError::InvalidPiece(c)),
                 }
             }
+            if file != FILE_NB {
+                return Err(FenError::RankLength(rank));
+            }
         }
 
-        match tokens.next().unwrap() {
-            "w" => pos.ctm = WHITE,
-            "b" => {
+        for &c in [WHITE, BLACK].iter() {
+            if !variant
+                .king_range(c)
+                .contains(&pos.piece_bb(KING, c).popcount())
+            {
+                return Err(FenError::KingCount(c));
+            }
+        }
+        let mut back_rank_pawns = pos.piecetype_bb(PAWN) & (RANK_1_BB | RANK_8_BB);
+        if variant == Variant::Horde {
+            back_rank_pawns &= !pos.piece_bb(PAWN, WHITE) | RANK_8_BB;
+        }
+        if back_rank_pawns.not_empty() {
+            return Err(FenError::PawnOnBackRank(back_rank_pawns.lsb()));
+        }
+
+        match tokens.next() {
+            Some("w") => pos.ctm = WHITE,
+            Some("b") => {
                 pos.ctm = BLACK;
                 pos.hash ^= hash::CTM;
             }
-            _ => panic!("Invalid color in FEN."),
+            Some(x) => return Err(FenError::InvalidColor(x.to_owned())),
+            None => return Err(FenError::MissingField("side to move")),
+        }
+        if pos.in_check(swap_color(pos.ctm)) {
+            return Err(FenError::OpponentInCheck);
         }
 
         ci.castle_rights = [ALL_CASTLING; 64];
-        let (w_rooks, b_rooks) = (pos.piece_bb(ROOK, WHITE), pos.piece_bb(ROOK, BLACK));
-        for c in tokens.next().unwrap().chars() {
-            match c {
-                'K' => pos.init_castle(ci, WHITE, file_of((w_rooks & RANK_1_BB).msb())),
-                'Q' => pos.init_castle(ci, WHITE, file_of((w_rooks & RANK_1_BB).lsb())),
-                'k' => pos.init_castle(ci, BLACK, file_of((b_rooks & RANK_8_BB).msb())),
-                'q' => pos.init_castle(ci, BLACK, file_of((b_rooks & RANK_8_BB).lsb())),
+        let castling = tokens
+            .next()
+            .ok_or(FenError::MissingField("castling rights"))?;
+        for c in castling.chars() {
+            let color = c.is_ascii_lowercase() as Color;
+            let back_rank = RANK_BB[relative_rank(RANK_1, color)];
+            let rooks = pos.piece_bb(ROOK, color) & back_rank;
+            if c == '-' {
+                break;
+            } else if variant == Variant::Antichess
+                || (pos.piece_bb(KING, color) & back_rank).is_empty()
+            {
+                return Err(FenError::InvalidCastling(c));
+            }
+            let king_file = file_of(pos.king_sq(color));
+            let rook_file = match c {
+                'K' | 'k' if rooks.not_empty() => file_of(rooks.msb()),
+                'Q' | 'q' if rooks.not_empty() => file_of(rooks.lsb()),
                 'a'..='h' | 'A'..='H' => {
-                    ci.frc = true; //Note that this does not cover all cases of FRC we could detect
-                    let color = c.is_ascii_lowercase() as Color;
-                    let file = char_to_file(c.to_ascii_lowercase());
-                    pos.init_castle(ci, color, file)
+                    ci.frc = true;
+                    char_to_file(c.to_ascii_lowercase())
                 }
-                '-' => break,
-                _ => panic!("Invalid castling rights in FEN."),
+                _ => return Err(FenError::InvalidCastling(c)),
             };
+            let wrong_side = match c {
+                'K' | 'k' => rook_file < king_file,
+                'Q' | 'q' => rook_file > king_file,
+                _ => false,
+            };
+            if (rooks & FILE_BB[rook_file]).is_empty() || wrong_side {
+                return Err(FenError::InvalidCastling(c));
+            }
+            pos.init_castle(ci, color, rook_file);
         }
 
         match tokens.next() {
             Some("-") => (),
             Some(ep) => {
-                pos.ep = str_to_square(ep);
+                let valid = ep.len() == 2
+                    && ('a'..='h').contains(&ep.chars().next().unwrap())
+                    && ep.chars().nth(1) == Some(rank_to_char(relative_rank(RANK_6, pos.ctm)));
+                if !valid {
+                    return Err(FenError::InvalidEnPassant(ep.to_owned()));
+                }
+                let sq = str_to_square(ep);
+                let origin = (sq as Direction + relative_dir(NORTH, pos.ctm)) as Square;
+                let pawn = make_piece(swap_color(pos.ctm), PAWN);
+                if pos.piece_on(ep_captured_sq(sq)) != Some(pawn)
+                    || pos.piece_on(sq).is_some()
+                    || pos.piece_on(origin).is_some()
+                {
+                    return Err(FenError::InvalidEnPassant(ep.to_owned()));
+                }
+                pos.ep = sq;
                 pos.hash ^= hash::EP[pos.ep as usize]
             }
-            _ => panic!("Invalid en passant in FEN."),
+            None => return Err(FenError::MissingField("en passant square")),
         }
 
-        pos.mr50 = tokens
-            .next()
-            .unwrap_or("0")
+        if variant == Variant::ThreeCheck && tokens.peek().is_some_and(|x| x.contains('+')) {
+            let field = tokens.next().unwrap();
+            let remaining = field
+                .split('+')
+                .map(|x| x.parse::<u8>().ok().filter(|&n| n <= CHECKS_TO_WIN))
+                .collect::<Option<Vec<u8>>>();
+            match remaining.as_deref() {
+                Some(&[white, black]) => {
+                    for (c, left) in [white, black].iter().enumerate() {
+                        pos.checks[c] = CHECKS_TO_WIN - left;
+                        pos.hash ^= hash::CHECKS[c][pos.checks[c] as usize];
+                    }
+                }
+                _ => return Err(FenError::InvalidChecks(field.to_owned())),
+            }
+        }
+
+        let counter = tokens.next().unwrap_or("0");
+        pos.mr50 = counter
             .parse()
-            .expect("Invalid halfmove counter in FEN.");
+            .map_err(|_| FenError::InvalidCounter(counter.to_owned()))?;
 
-        pos.fullmove = tokens
-            .next()
-            .unwrap_or("1")
+        let counter = tokens.next().unwrap_or("1");
+        pos.fullmove = counter
             .parse()
-            .expect("Invalid fullmove counter in FEN.");
+            .map_err(|_| FenError::InvalidCounter(counter.to_owned()))?;
 
-        (pos, cinfo)
+        Ok((pos, cinfo))
+    }
+
+    // Chess960 castling rights are written with the rook files (Shredder-FEN)
+    pub fn to_fen(&self, ci: &CastleInfo) -> String {
+        let mut fen = String::new();
+        for rank in (RANK_1..RANK_NB).rev() {
+            let mut empty = 0;
+            for file in FILE_A..FILE_NB {
+                match self.piece_on(to_square(rank, file)) {
+                    Some(piece) => {
+                        if empty > 0 {
+                            fen.push_str(&empty.to_string());
+                            empty = 0;
+                        }
+                        fen.push(piece_to_char(piece));
+                    }
+                    None => empty += 1,
+                }
+            }
+            if empty > 0 {
+                fen.push_str(&empty.to_string());
+            }
+            if rank != RANK_1 {
+                fen.push('/');
+            }
+        }
+
+        fen.push_str([" w ", " b "][self.ctm as usize]);
+
+        let mut castling = String::new();
+        for &cr in [W_KS, W_QS, B_KS, B_QS].iter() {
+            if self.cr & cr == 0 {
+                continue;
+            }
+            let c = if ci.frc {
+                file_to_char(file_of(ci.castle_rooks[cr as usize]))
+            } else if cr & KS_CASTLING != 0 {
+                'k'
+            } else {
+                'q'
+            };
+            castling.push(if cr & W_CASTLING != 0 {
+                c.to_ascii_uppercase()
+            } else {
+                c
+            });
+        }
+        fen.push_str(if castling.is_empty() { "-" } else { &castling });
+
+        let ep = if self.ep == A1 {
+            "-".to_owned()
+        } else {
+            square_to_str(self.ep)
+        };
+        fen.push_str(&format!(" {}", ep));
+        if self.variant == Variant::ThreeCheck {
+            let remaining = |c: usize| CHECKS_TO_WIN - self.checks[c];
+            fen.push_str(&format!(" {}+{}", remaining(0), remaining(1)));
+        }
+        fen.push_str(&format!(" {} {}", self.mr50, self.fullmove));
+        fen
     }
 
     fn init_castle(&mut self, ci: &mut CastleInfo, color: Color, file: File) {
@@ -324,12 +1132,29 @@ impl Position {
         self.cr |= cr;
         self.hash ^= hash::CASTLE_RIGHTS[self.cr as usize];
         ci.castle_rooks[cr as usize] = rook_sq;
+        // Standard notation can only describe castling with the king on e and rooks on a or h
+        ci.frc |= king_file != FILE_E || (file != FILE_A && file != FILE_H);
         ci.castle_rights[rook_sq as usize] &= !cr;
         ci.castle_rights[king_sq as usize] &= !cr;
         ci.castle_path[cr as usize] = between_inc_bb(king_sq, CASTLE_K_TARGET[cr as usize])
             | between_inc_bb(rook_sq, CASTLE_R_TARGET[cr as usize]);
     }
 
+    // Builds a position without castling rights or en passant square from a list of pieces
+    pub fn from_pieces(pieces: &[(Piece, Square)], ctm: Color) -> Position {
+        let mut pos = Position::default();
+        for &(piece, sq) in pieces.iter() {
+            pos.toggle_piece_on_sq(piece, sq);
+            pos.add_piece_eval(piece, sq);
+        }
+        if ctm == BLACK {
+            pos.ctm = BLACK;
+            pos.hash ^= hash::CTM;
+        }
+        pos.fullmove = 1;
+        pos
+    }
+
     fn add_piece(&mut self, piece_char: char, sq: Square) {
         let piece = char_to_piece(piece_char);
         self.toggle_piece_on_sq(piece, sq);
@@ -346,10 +1171,70 @@ impl Position {
 
     pub fn startpos() -> (Position, CastleInfo) {
         let startpos_fen = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
-        Position::parse_fen(startpos_fen)
+        Position::from_fen(startpos_fen).unwrap()
+    }
+
+    // Chess960 start position by Scharnagl number, 518 is the standard setup
+    pub fn frc_startpos(n: u16) -> (Position, CastleInfo) {
+        Position::dfrc_startpos(n, n)
+    }
+
+    // Double Chess960 start position with independent back ranks for white and black
+    pub fn dfrc_startpos(white_n: u16, black_n: u16) -> (Position, CastleInfo) {
+        let (white, black) = (frc_back_rank(white_n), frc_back_rank(black_n));
+        let rook_files = |rank: &[char; 8]| {
+            let rooks = (0..8).filter(|&f| rank[f] == 'r').collect::<Vec<File>>();
+            (file_to_char(rooks[1]), file_to_char(rooks[0]))
+        };
+        let ((wk, wq), (bk, bq)) = (rook_files(&white), rook_files(&black));
+        let fen = format!(
+            "{}/pppppppp/8/8/8/8/PPPPPPPP/{} w {}{}{}{} - 0 1",
+            black.iter().collect::<String>(),
+            white.iter().collect::<String>().to_ascii_uppercase(),
+            wk.to_ascii_uppercase(),
+            wq.to_ascii_uppercase(),
+            bk,
+            bq
+        );
+        Position::from_fen(&fen).unwrap()
     }
 }
 
+// Knight placements on the five squares left after placing the bishops and the queen
+const FRC_KNIGHTS: [(usize, usize); 10] = [
+    (0, 1),
+    (0, 2),
+    (0, 3),
+    (0, 4),
+    (1, 2),
+    (1, 3),
+    (1, 4),
+    (2, 3),
+    (2, 4),
+    (3, 4),
+];
+
+fn frc_back_rank(n: u16) -> [char; 8] {
+    debug_assert!(n < 960);
+    let n = n as usize;
+    let mut rank = [' '; 8];
+    rank[2 * (n % 4) + 1] = 'b';
+    rank[2 * (n / 4 % 4)] = 'b';
+
+    let mut place = |piece: char, idx: usize| {
+        let sq = (0..8).filter(|&f| rank[f] == ' ').nth(idx).unwrap();
+        rank[sq] = piece;
+    };
+    place('q', n / 16 % 6);
+    let (n1, n2) = FRC_KNIGHTS[n / 96];
+    place('n', n2);
+    place('n', n1);
+    place('r', 0);
+    place('k', 0);
+    place('r', 0);
+    rank
+}
+
 impl fmt::Display for Position {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         let mut res = String::default();
@@ -379,8 +1264,288 @@ impl Default for Position {
             cr: 0,
             fullmove: 0,
 
+            variant: Variant::Standard,
+            checks: [0; 2],
+
             hash: 0,
             piece_eval: 0,
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bench::BENCHMARKING_POSITIONS;
+    use crate::rng::Rng;
+    use crate::variant::VARIANTS;
+
+    fn assert_round_trip(pos: &Position, ci: &CastleInfo) {
+        let fen = pos.to_fen(ci);
+        let (new_pos, new_ci) = Position::from_variant_fen(&fen, pos.variant).unwrap();
+        assert_eq!(new_pos.to_fen(&new_ci), fen);
+        assert_eq!(new_pos.hash, pos.hash, "{}", fen);
+    }
+
+    fn sorted(moves: impl Iterator<Item = Move>, ci: &CastleInfo) -> Vec<String> {
+        let mut moves = moves.map(|m| m.to_str(ci)).collect::<Vec<String>>();
+        moves.sort();
+        moves
+    }
+
+    fn legal(pos: &Position, ci: &CastleInfo, moves: MoveList) -> Vec<String> {
+        sorted(moves.filter(|&m| pos.clone().make_move(m, ci)), ci)
+    }
+
+    fn check_staged_generation(pos: &Position, ci: &CastleInfo, depth: u8) {
+        assert_eq!(
+            sorted(pos.gen_captures(ci).chain(pos.gen_quiets(ci)), ci),
+            sorted(pos.gen_pseudo_legals(ci), ci),
+            "{}",
+            pos.to_fen(ci)
+        );
+        if pos.in_check(pos.ctm) {
+            assert_eq!(
+                legal(pos, ci, pos.gen_evasions(ci)),
+                legal(pos, ci, pos.gen_pseudo_legals(ci)),
+                "{}",
+                pos.to_fen(ci)
+            );
+        }
+        for mv in pos.gen_checks(ci) {
+            assert!(
+                pos.gives_check(mv, ci),
+                "{} {}",
+                pos.to_fen(ci),
+                mv.to_str(ci)
+            );
+        }
+
+        if depth == 0 {
+            return;
+        }
+        for mv in pos.gen_legals(ci) {
+            let mut new_pos = pos.clone();
+            new_pos.make_move(mv, ci);
+            check_staged_generation(&new_pos, ci, depth - 1);
+        }
+    }
+
+    #[test]
+    fn staged_generation() {
+        for &fen in BENCHMARKING_POSITIONS.iter() {
+            let (pos, ci) = Position::from_fen(fen).unwrap();
+            check_staged_generation(&pos, &ci, 2);
+        }
+    }
+
+    // Plays random pseudo-legal moves with make/unmake, validating the position after each step
+    fn random_playout(pos: &mut Position, ci: &CastleInfo, rng: &mut Rng, plies: usize) {
+        let fen = pos.to_fen(ci);
+        let mut stack = Vec::new();
+        for _ in 0..plies {
+            let moves = pos.gen_pseudo_legals(ci).collect::<Vec<Move>>();
+            if moves.is_empty() || pos.is_variant_loss() {
+                break;
+            }
+            let mv = moves[rng.below(moves.len())];
+            let hash = pos.hash;
+            match pos.make_move_undo(mv, ci) {
+                Some(undo) => stack.push((mv, undo)),
+                None => assert_eq!(pos.hash, hash),
+            }
+            assert_eq!(
+                pos.validate(ci),
+                Ok(()),
+                "{} {}",
+                pos.to_fen(ci),
+                mv.to_str(ci)
+            );
+        }
+        while let Some((mv, undo)) = stack.pop() {
+            pos.unmake_move(mv, ci, &undo);
+            assert_eq!(
+                pos.validate(ci),
+                Ok(()),
+                "{} {}",
+                pos.to_fen(ci),
+                mv.to_str(ci)
+            );
+        }
+        assert_eq!(pos.to_fen(ci), fen);
+    }
+
+    #[test]
+    fn make_unmake_fuzz() {
+        let mut rng = Rng::new(0x5CA3);
+        let mut positions = BENCHMARKING_POSITIONS
+            .iter()
+            .map(|fen| Position::from_fen(fen).unwrap())
+            .collect::<Vec<(Position, CastleInfo)>>();
+        positions.extend([0, 518, 959].iter().map(|&n| Position::frc_startpos(n)));
+        positions.push(Position::dfrc_startpos(100, 700));
+        for &variant in VARIANTS.iter() {
+            positions.push(Position::from_variant_fen(variant.start_fen(), variant).unwrap());
+        }
+        for (pos, ci) in positions.iter_mut() {
+            for _ in 0..20 {
+                random_playout(pos, ci, &mut rng, 100);
+            }
+        }
+    }
+
+    // Pawn moves, knight moves to the third rank and castlings whose king and rook paths
+    // only contain the castling pieces. Depending on the setup this is 18 to 21 moves.
+    fn frc_legal_count(rank: &[char]) -> usize {
+        let knights = (0..8).filter(|&f| rank[f] == 'n');
+        let knight_moves = knights
+            .map(|f| (f > 0) as usize + (f < 7) as usize)
+            .sum::<usize>();
+        let king = rank.iter().position(|&c| c == 'k').unwrap();
+        let rooks = (0..8).filter(|&f| rank[f] == 'r').collect::<Vec<usize>>();
+        let castlings = [(rooks[0], 2, 3), (rooks[1], 6, 5)]
+            .iter()
+            .filter(|&&(rook, k_target, r_target)| {
+                let span = |a: usize, b: usize| a.min(b)..=a.max(b);
+                span(king, k_target)
+                    .chain(span(rook, r_target))
+                    .all(|f| f == king || f == rook)
+            })
+            .count();
+        16 + knight_moves + castlings
+    }
+
+    #[test]
+    fn frc_start_positions() {
+        let classical = Position::startpos();
+        let (pos, ci) = Position::frc_startpos(518);
+        assert_eq!(pos.hash, classical.0.hash);
+        assert_eq!(
+            pos.to_fen(&ci),
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w HAha - 0 1"
+        );
+        let (pos, ci) = Position::frc_startpos(0);
+        assert!(pos
+            .to_fen(&ci)
+            .starts_with("bbqnnrkr/pppppppp/8/8/8/8/PPPPPPPP/BBQNNRKR w "));
+
+        let mut counts = [0; 4];
+        for n in 0..960 {
+            let (pos, ci) = Position::frc_startpos(n);
+            assert_round_trip(&pos, &ci);
+            let fen = pos.to_fen(&ci);
+            let rank = fen
+                .split('/')
+                .next()
+                .unwrap()
+                .chars()
+                .collect::<Vec<char>>();
+            let legals = pos.gen_legals(&ci).len();
+            assert_eq!(legals, frc_legal_count(&rank), "{} {}", n, fen);
+            counts[legals - 18] += 1;
+        }
+        // Most setups have the 20 moves of the classical position
+        assert!(counts[2] > counts[0] + counts[1] + counts[3]);
+    }
+
+    #[test]
+    fn fen_round_trip() {
+        for &fen in BENCHMARKING_POSITIONS.iter() {
+            let (pos, ci) = Position::from_fen(fen).unwrap();
+            assert_eq!(pos.to_fen(&ci), fen);
+            assert_round_trip(&pos, &ci);
+        }
+        // Shredder-FEN castling rights
+        for &n in [0, 518, 959].iter() {
+            let (pos, ci) = Position::frc_startpos(n);
+            assert!(ci.frc);
+            assert_round_trip(&pos, &ci);
+        }
+        let three_check = "r1bqkbnr/pppp1ppp/2n5/4p3/2B1P3/8/PPPP1PPP/RNBQK1NR w KQkq - 2+3 2 3";
+        let (pos, ci) = Position::from_variant_fen(three_check, Variant::ThreeCheck).unwrap();
+        assert_eq!(pos.to_fen(&ci), three_check);
+        assert_round_trip(&pos, &ci);
+    }
+
+    #[test]
+    fn fen_errors() {
+        use FenError::*;
+        let err = |fen: &str, variant| Position::from_variant_fen(fen, variant).err();
+        let std = |fen: &str| err(fen, Variant::Standard);
+        let cases = [
+            ("", MissingField("piece placement")),
+            ("8/8/8/8/8/8/8/4K2k", MissingField("side to move")),
+            ("8/8/8/8/8/8/8/4K2k w", MissingField("castling rights")),
+            ("8/8/8/8/8/8/8/4K2k w -", MissingField("en passant square")),
+            ("8/8/8/8/8/8/4K2k w - - 0 1", RankCount(7)),
+            ("8/8/8/8/8/8/8/8/4K2k w - - 0 1", RankCount(9)),
+            ("8/8/8/8/8/8/7/4K2k w - - 0 1", RankLength(RANK_2)),
+            ("81/8/8/8/8/8/8/4K2k w - - 0 1", RankLength(RANK_8)),
+            ("8/8/8/8/8/8/8/4K2kp w - - 0 1", RankLength(RANK_1)),
+            ("8/8/8/8/8/3x4/8/4K2k w - - 0 1", InvalidPiece('x')),
+            ("8/8/8/8/8/8/8/4K3 w - - 0 1", KingCount(BLACK)),
+            ("8/8/8/8/8/8/8/K3K2k w - - 0 1", KingCount(WHITE)),
+            ("P7/8/8/8/8/8/8/4K2k w - - 0 1", PawnOnBackRank(A8)),
+            ("8/8/8/8/8/8/8/3pK2k w - - 0 1", PawnOnBackRank(D1)),
+            ("8/8/8/8/8/8/8/4K2k x - - 0 1", InvalidColor("x".to_owned())),
+            (
+                "8/8/8/8/8/8/8/4K2k white - - 0 1",
+                InvalidColor("white".to_owned()),
+            ),
+            ("8/8/8/8/8/8/8/4K1Rk w - - 0 1", OpponentInCheck),
+            ("4k3/8/8/8/8/8/8/4K3 w K - 0 1", InvalidCastling('K')),
+            ("4k3/8/8/8/8/8/8/R3K3 w K - 0 1", InvalidCastling('K')),
+            ("4k3/8/8/8/8/8/8/4K2R w Kq - 0 1", InvalidCastling('q')),
+            ("4k3/8/8/8/8/8/8/4K2R w X - 0 1", InvalidCastling('X')),
+            ("4k3/8/8/8/8/8/8/4K2R w G - 0 1", InvalidCastling('G')),
+            ("4k3/8/8/8/8/8/4K3/7R w H - 0 1", InvalidCastling('H')),
+            (
+                "4k3/8/8/8/8/8/8/4K3 w - e6 0 1",
+                InvalidEnPassant("e6".to_owned()),
+            ),
+            (
+                "4k3/8/8/4p3/8/8/8/4K3 w - e3 0 1",
+                InvalidEnPassant("e3".to_owned()),
+            ),
+            (
+                "4k3/8/8/4p3/8/8/8/4K3 w - e9 0 1",
+                InvalidEnPassant("e9".to_owned()),
+            ),
+            (
+                "4k3/8/4p3/4p3/8/8/8/4K3 w - e6 0 1",
+                InvalidEnPassant("e6".to_owned()),
+            ),
+            (
+                "4k3/8/8/8/8/8/8/4K3 w - - x 1",
+                InvalidCounter("x".to_owned()),
+            ),
+            (
+                "4k3/8/8/8/8/8/8/4K3 w - - 0 -1",
+                InvalidCounter("-1".to_owned()),
+            ),
+        ];
+        for (fen, expected) in cases.iter() {
+            assert_eq!(std(fen).as_ref(), Some(expected), "{}", fen);
+        }
+
+        // Variant specific rules
+        let horde = "4k3/8/8/8/8/8/8/PPPPPPPP w - - 0 1";
+        assert_eq!(err(horde, Variant::Horde), None);
+        assert_eq!(std(horde), Some(KingCount(WHITE)));
+        let antichess = "4k3/8/8/8/8/8/8/R3K3 w Q - 0 1";
+        assert_eq!(err(antichess, Variant::Standard), None);
+        assert_eq!(
+            err(antichess, Variant::Antichess),
+            Some(InvalidCastling('Q'))
+        );
+        let three_check = "4k3/8/8/8/8/8/8/4K3 w - - 4+1 0 1";
+        assert_eq!(
+            err(three_check, Variant::ThreeCheck),
+            Some(InvalidChecks("4+1".to_owned()))
+        );
+        assert_eq!(
+            err("4k3/8/8/8/8/8/8/4K3 w - - 1+x 0 1", Variant::ThreeCheck),
+            Some(InvalidChecks("1+x".to_owned()))
+        );
+    }
+}
@@ -0,0 +1,32 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// xorshift64*, http://vigna.di.unimi.it/ftp/papers/xorshift.pdf
+pub struct Rng(u64);
+
+impl Rng {
+    pub fn new(seed: u64) -> Rng {
+        Rng(seed | 1)
+    }
+
+    // Seeded from the system clock
+    pub fn from_time() -> Rng {
+        Rng::new(time_seed())
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(2685821657736338717u64)
+    }
+
+    pub fn below(&mut self, n: usize) -> usize {
+        (self.next_u64() % n as u64) as usize
+    }
+}
+
+pub fn time_seed() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(1070372, |x| x.as_nanos() as u64)
+}
@@ -1,10 +1,13 @@
+use crate::bitbase;
 use crate::eval::eval;
 use crate::movepicker::MovePicker;
 use crate::position::*;
 use crate::r#move::*;
+use crate::syzygy::{WDL_LOSS, WDL_WIN};
 use crate::thread::Thread;
 use crate::transposition::{FLAG_EXACT, FLAG_LOWER, FLAG_UPPER};
 use crate::types::*;
+use crate::variant::Variant;
 
 use std::sync::atomic::Ordering;
 use std::time::Instant;
@@ -25,9 +28,11 @@ pub struct Limits {
 
     pub depth: u8,
     pub mate: i32,
+    pub nodes: u64,
 
     pub is_time_limit: bool,
     pub is_infinite: bool,
+    pub is_silent: bool,
 }
 
 impl Limits {
@@ -58,26 +63,36 @@ fn print_thinking(thread: &mut Thread, depth: u8, score: Score) {
     let nodes = thread.get_global_nodes();
     let nps = (nodes as f64 * 1000.0 / (elapsed as f64 + 1.0)) as u64;
     let hashfull = thread.tt().hashfull();
+    let tbhits = thread.get_global_tbhits();
     let pv = thread.best_move.to_str(&thread.ci);
     println!(
-        "info depth {} score {} {} time {} nodes {} nps {} hashfull {} pv {}",
-        depth, score_type, score, elapsed, nodes, nps, hashfull, pv
+        "info depth {} score {} {} time {} nodes {} nps {} hashfull {} tbhits {} pv {}",
+        depth, score_type, score, elapsed, nodes, nps, hashfull, tbhits, pv
     );
 }
 
 pub fn start_search(thread: &mut Thread) {
-    if thread.id == 0 {
+    let verbose = thread.id == 0 && !thread.limits.is_silent;
+    if verbose {
         println!("info string static eval {}", eval(&thread.root));
     }
+    // Root moves restricted by the caller are kept as they are
+    if thread.root_moves.is_empty() && thread.tb.can_probe(&thread.root) {
+        let root_moves = thread.tb.root_probe(&thread.root, &thread.ci);
+        thread.root_moves = root_moves.unwrap_or_default();
+    }
     for d in 1..=thread.limits.depth {
         let pos = thread.root.clone();
         let score = search(thread, pos, d, 0, -INFINITE, INFINITE);
-        if thread.id == 0 && !thread.abort.load(Ordering::Relaxed) {
-            print_thinking(thread, d, score);
+        if !thread.abort.load(Ordering::Relaxed) {
+            thread.best_score = score;
+            if verbose {
+                print_thinking(thread, d, score);
+            }
         }
     }
 
-    if thread.id == 0 {
+    if verbose {
         println!("bestmove {}", thread.best_move.to_str(&thread.ci));
     }
 }
@@ -94,7 +109,9 @@ fn search(
     let root = height == 0;
     let original_alpha = alpha;
 
-    if thread.get_local_nodes() % CHECKUP_NODES == 0 && thread.limits.should_stop() {
+    if thread.get_local_nodes().is_multiple_of(CHECKUP_NODES) && thread.limits.should_stop()
+        || thread.limits.nodes != 0 && thread.get_local_nodes() >= thread.limits.nodes
+    {
         thread.abort.store(true, Ordering::Relaxed);
     }
 
@@ -102,6 +119,37 @@ fn search(
         return 0;
     }
 
+    if pos.is_variant_loss() {
+        return mate_score(height);
+    }
+
+    // Endgame tables only cover standard chess
+    if !root && pos.variant == Variant::Standard {
+        if let Some(score) = thread.dtm.probe(&pos, height) {
+            thread.inc_tbhits();
+            return score;
+        }
+
+        if let Some(score) = bitbase::probe_kpk(&pos) {
+            return score;
+        }
+
+        if pos.mr50 == 0 && thread.tb.can_probe(&pos) {
+            if let Some(wdl) = thread.tb.probe_wdl(&pos, &thread.ci) {
+                thread.inc_tbhits();
+                let score = match wdl {
+                    WDL_WIN => tb_win_score(height),
+                    WDL_LOSS => -tb_win_score(height),
+                    _ => 0,
+                };
+                thread
+                    .tt()
+                    .insert(&pos, score, height, NO_MOVE, MAX_DEPTH, FLAG_EXACT);
+                return score;
+            }
+        }
+    }
+
     if depth == 0 {
         return eval(&pos);
     }
@@ -127,6 +175,10 @@ fn search(
     let mut move_picker = MovePicker::new(&pos, tt_move);
 
     while let Some(mv) = move_picker.next(&thread.ci) {
+        if root && !thread.root_moves.is_empty() && !thread.root_moves.contains(&mv) {
+            continue;
+        }
+
         let mut new_pos = pos.clone();
         if !new_pos.make_move(mv, &thread.ci) {
             continue;
@@ -149,11 +201,7 @@ fn search(
     }
 
     if move_count == 0 {
-        return if pos.in_check(pos.ctm) {
-            mate_score(height)
-        } else {
-            0
-        };
+        return pos.no_moves_score(height);
     }
 
     if !thread.abort.load(Ordering::Relaxed) {
@@ -189,9 +237,11 @@ impl Default for Limits {
 
             depth: MAX_DEPTH,
             mate: 0,
+            nodes: 0,
 
             is_time_limit: false,
             is_infinite: false,
+            is_silent: false,
         }
     }
 }
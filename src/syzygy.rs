@@ -0,0 +1,1061 @@
+use crate::bitboard::*;
+use crate::endgame::{signature, MaterialKey};
+use crate::position::{CastleInfo, Position};
+use crate::r#move::*;
+use crate::types::*;
+use crate::variant::Variant;
+
+use memmap2::Mmap;
+use std::collections::HashMap;
+use std::fs::File;
+use std::path::Path;
+
+/*
+Probing code for Syzygy tablebases, following the layout of the reference implementation.
+A table file consists of:
+  magic (4 byte) | flags (1 byte) | per file: piece order and pieces | sizes and huffman trees
+  | dtz map (only .rtbz) | sparse indices | block lengths | 64 byte aligned compressed blocks
+*/
+
+pub const TB_PIECES: usize = 7;
+
+const WDL_MAGIC: [u8; 4] = [0x71, 0xE8, 0x23, 0x5D];
+const DTZ_MAGIC: [u8; 4] = [0xD7, 0x66, 0x0C, 0xA5];
+
+// Flags of a single PairsData
+const FLAG_STM: u8 = 1;
+const FLAG_MAPPED: u8 = 2;
+const FLAG_WIN_PLIES: u8 = 4;
+const FLAG_LOSS_PLIES: u8 = 8;
+const FLAG_WIDE: u8 = 16;
+const FLAG_SINGLE_VALUE: u8 = 128;
+
+pub type Wdl = i32;
+
+pub const WDL_LOSS: Wdl = -2;
+pub const WDL_BLESSED_LOSS: Wdl = -1;
+pub const WDL_DRAW: Wdl = 0;
+pub const WDL_CURSED_WIN: Wdl = 1;
+pub const WDL_WIN: Wdl = 2;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum ProbeState {
+    Fail,
+    Ok,
+    ChangeStm,
+    ZeroingBestMove,
+}
+
+const fn off_a1h8(sq: Square) -> i32 {
+    rank_of(sq) as i32 - file_of(sq) as i32
+}
+
+const fn flip_file(sq: Square) -> Square {
+    sq ^ 7
+}
+
+const fn flip_rank(sq: Square) -> Square {
+    sq ^ 56
+}
+
+// Encodes a square below the a1-h8 diagonal to 0..27
+const MAP_B1H1H7: [usize; 64] = {
+    let mut res = [0; 64];
+    let (mut sq, mut code) = (0, 0);
+    while sq < SQUARE_NB {
+        if off_a1h8(sq as Square) < 0 {
+            res[sq] = code;
+            code += 1;
+        }
+        sq += 1;
+    }
+    res
+};
+
+// Encodes a square in the a1-d1-d4 triangle to 0..9, diagonal squares last
+const MAP_A1D1D4: [usize; 64] = {
+    let mut res = [0; 64];
+    let (mut sq, mut code) = (A1, 0);
+    while sq <= D4 {
+        if off_a1h8(sq) < 0 && file_of(sq) <= FILE_D {
+            res[sq as usize] = code;
+            code += 1;
+        }
+        sq += 1;
+    }
+    sq = A1;
+    while sq <= D4 {
+        if off_a1h8(sq) == 0 && file_of(sq) <= FILE_D {
+            res[sq as usize] = code;
+            code += 1;
+        }
+        sq += 1;
+    }
+    res
+};
+
+// Encodes the 462 legal placements of two kings, where the first is in the a1-d1-d4 triangle
+const MAP_KK: [[usize; 64]; 10] = {
+    let mut res = [[0; 64]; 10];
+    let mut code = 0;
+    let mut pass = 0;
+    // Positions with both kings on the diagonal are encoded last
+    while pass < 2 {
+        let mut idx = 0;
+        while idx < 10 {
+            let mut s1 = A1;
+            while s1 <= D4 {
+                if MAP_A1D1D4[s1 as usize] == idx && (idx != 0 || s1 == B1) {
+                    let mut s2 = A1;
+                    while s2 <= H8 {
+                        let illegal = distance(s1, s2) <= 1;
+                        let above = off_a1h8(s1) == 0 && off_a1h8(s2) > 0;
+                        let both_diag = off_a1h8(s1) == 0 && off_a1h8(s2) == 0;
+                        if !illegal && !above && both_diag == (pass == 1) {
+                            res[idx][s2 as usize] = code;
+                            code += 1;
+                        }
+                        s2 += 1;
+                    }
+                }
+                s1 += 1;
+            }
+            idx += 1;
+        }
+        pass += 1;
+    }
+    res
+};
+
+const BINOMIAL: [[u64; 64]; TB_PIECES] = {
+    let mut res = [[0; 64]; TB_PIECES];
+    res[0][0] = 1;
+    let mut n = 1;
+    while n < 64 {
+        let mut k = 0;
+        while k < TB_PIECES && k <= n {
+            res[k][n] =
+                if k > 0 { res[k - 1][n - 1] } else { 0 } + if k < n { res[k][n - 1] } else { 0 };
+            k += 1;
+        }
+        n += 1;
+    }
+    res
+};
+
+// Encodes a2-h7 to 0..47, the leading pawn is the one with the highest value
+const MAP_PAWNS: [usize; 64] = {
+    let mut res = [0; 64];
+    let mut used = 0;
+    let mut file = FILE_A;
+    while file <= FILE_D {
+        let mut rank = RANK_2;
+        while rank <= RANK_7 {
+            let sq = to_square(rank, file);
+            res[sq as usize] = 47 - used;
+            res[flip_file(sq) as usize] = 46 - used;
+            used += 2;
+            rank += 1;
+        }
+        file += 1;
+    }
+    res
+};
+
+// Per number of leading pawns: the index of a leading pawn square and the size per file
+const LEAD_PAWNS: ([[u64; 64]; 6], [[u64; 4]; 6]) = {
+    let mut idx_table = [[0; 64]; 6];
+    let mut size_table = [[0; 4]; 6];
+    let mut count = 1;
+    while count <= 5 {
+        let mut file = FILE_A;
+        while file <= FILE_D {
+            let mut idx = 0;
+            let mut rank = RANK_2;
+            while rank <= RANK_7 {
+                let sq = to_square(rank, file) as usize;
+                idx_table[count][sq] = idx;
+                idx += BINOMIAL[count - 1][MAP_PAWNS[sq]];
+                rank += 1;
+            }
+            size_table[count][file] = idx;
+            file += 1;
+        }
+        count += 1;
+    }
+    (idx_table, size_table)
+};
+
+fn read_u8(data: &[u8], off: usize) -> u8 {
+    data.get(off).copied().unwrap_or(0)
+}
+
+fn read_u16_le(data: &[u8], off: usize) -> u16 {
+    read_u8(data, off) as u16 | (read_u8(data, off + 1) as u16) << 8
+}
+
+fn read_u32_le(data: &[u8], off: usize) -> u32 {
+    read_u16_le(data, off) as u32 | (read_u16_le(data, off + 2) as u32) << 16
+}
+
+fn read_u32_be(data: &[u8], off: usize) -> u32 {
+    (0..4).fold(0, |acc, i| acc << 8 | read_u8(data, off + i) as u32)
+}
+
+fn read_u64_be(data: &[u8], off: usize) -> u64 {
+    (read_u32_be(data, off) as u64) << 32 | read_u32_be(data, off + 4) as u64
+}
+
+// Decoding information for one side to move and leading file. Offsets point into the table file.
+#[derive(Clone, Default)]
+struct PairsData {
+    flags: u8,
+    block_size: usize,
+    span: usize,
+    num_blocks: usize,
+    max_sym_len: usize,
+    min_sym_len: usize,
+    lowest_sym: usize,
+    btree: usize,
+    block_length: usize,
+    block_length_size: usize,
+    sparse_index: usize,
+    sparse_index_size: usize,
+    data: usize,
+    base64: Vec<u64>,
+    symlen: Vec<u8>,
+    pieces: [Piece; TB_PIECES],
+    group_idx: [u64; TB_PIECES + 1],
+    group_len: [usize; TB_PIECES + 1],
+    map_idx: [usize; 4],
+}
+
+struct TableData {
+    mmap: Mmap,
+    items: Vec<Vec<PairsData>>, // [side][file]
+    map: usize,
+}
+
+// Material configuration of a table, parsed from its name
+struct Material {
+    key: MaterialKey,
+    key2: MaterialKey,
+    piece_count: usize,
+    has_pawns: bool,
+    has_unique_pieces: bool,
+    pawn_count: [usize; 2],
+}
+
+struct Table {
+    mat: Material,
+    wdl: TableData,
+    dtz: Option<TableData>,
+}
+
+#[derive(Default)]
+pub struct Tablebases {
+    tables: Vec<Table>,
+    keys: HashMap<MaterialKey, usize>,
+    pub max_pieces: u32,
+}
+
+impl TableData {
+    fn new(mmap: Mmap, mat: &Material, is_dtz: bool) -> Option<TableData> {
+        let magic = if is_dtz { DTZ_MAGIC } else { WDL_MAGIC };
+        if mmap.get(0..4)? != magic {
+            return None;
+        }
+
+        let mut td = TableData {
+            mmap,
+            items: Vec::new(),
+            map: 0,
+        };
+        let bytes = &td.mmap;
+        let mut data = 5; // Magic and flags
+
+        let sides = if !is_dtz && mat.key != mat.key2 { 2 } else { 1 };
+        let files = if mat.has_pawns { 4 } else { 1 };
+        let pp = mat.has_pawns && mat.pawn_count[1] > 0;
+        let mut items = vec![vec![PairsData::default(); files]; sides];
+
+        for f in 0..files {
+            let (b0, b1) = (read_u8(bytes, data), read_u8(bytes, data + 1));
+            let order = [
+                [b0 & 0xF, if pp { b1 & 0xF } else { 0xF }],
+                [b0 >> 4, if pp { b1 >> 4 } else { 0xF }],
+            ];
+            data += 1 + pp as usize;
+
+            for k in 0..mat.piece_count {
+                let b = read_u8(bytes, data);
+                for (i, side) in items.iter_mut().enumerate() {
+                    side[f].pieces[k] = if i == 1 { b >> 4 } else { b & 0xF };
+                }
+                data += 1;
+            }
+
+            for (i, side) in items.iter_mut().enumerate() {
+                mat.set_groups(&mut side[f], order[i], f);
+            }
+        }
+        data += data & 1;
+
+        for f in 0..files {
+            for side in items.iter_mut() {
+                data = td.set_sizes(&mut side[f], data);
+            }
+        }
+
+        if is_dtz {
+            td.map = data;
+            for d in items[0].iter_mut() {
+                if d.flags & FLAG_MAPPED == 0 {
+                    continue;
+                }
+                if d.flags & FLAG_WIDE != 0 {
+                    data += data & 1;
+                    for i in 0..4 {
+                        d.map_idx[i] = (data - td.map) / 2 + 1;
+                        data += 2 * read_u16_le(bytes, data) as usize + 2;
+                    }
+                } else {
+                    for i in 0..4 {
+                        d.map_idx[i] = data - td.map + 1;
+                        data += read_u8(bytes, data) as usize + 1;
+                    }
+                }
+            }
+            data += data & 1;
+        }
+
+        for f in 0..files {
+            for side in items.iter_mut() {
+                side[f].sparse_index = data;
+                data += side[f].sparse_index_size * 6;
+            }
+        }
+        for f in 0..files {
+            for side in items.iter_mut() {
+                side[f].block_length = data;
+                data += side[f].block_length_size * 2;
+            }
+        }
+        for f in 0..files {
+            for side in items.iter_mut() {
+                data = (data + 0x3F) & !0x3F;
+                side[f].data = data;
+                data += side[f].num_blocks * side[f].block_size;
+            }
+        }
+
+        td.items = items;
+        Some(td)
+    }
+
+    fn btree_left(&self, d: &PairsData, sym: usize) -> usize {
+        let off = d.btree + 3 * sym;
+        ((read_u8(&self.mmap, off + 1) as usize & 0xF) << 8) | read_u8(&self.mmap, off) as usize
+    }
+
+    fn btree_right(&self, d: &PairsData, sym: usize) -> usize {
+        let off = d.btree + 3 * sym;
+        (read_u8(&self.mmap, off + 2) as usize) << 4 | read_u8(&self.mmap, off + 1) as usize >> 4
+    }
+
+    fn set_symlen(&self, d: &mut PairsData, sym: usize, visited: &mut Vec<bool>) -> u8 {
+        visited[sym] = true;
+        let right = self.btree_right(d, sym);
+        if right == 0xFFF {
+            return 0;
+        }
+        let left = self.btree_left(d, sym);
+        for &s in [left, right].iter() {
+            if !visited[s] {
+                d.symlen[s] = self.set_symlen(d, s, visited);
+            }
+        }
+        d.symlen[left].wrapping_add(d.symlen[right]).wrapping_add(1)
+    }
+
+    fn set_sizes(&self, d: &mut PairsData, mut data: usize) -> usize {
+        let bytes = &self.mmap;
+        d.flags = read_u8(bytes, data);
+        data += 1;
+
+        if d.flags & FLAG_SINGLE_VALUE != 0 {
+            d.min_sym_len = read_u8(bytes, data) as usize;
+            return data + 1;
+        }
+
+        let group_nb = d.group_len.iter().position(|&x| x == 0).unwrap();
+        let tb_size = d.group_idx[group_nb];
+
+        d.block_size = 1 << read_u8(bytes, data);
+        d.span = 1 << read_u8(bytes, data + 1);
+        d.sparse_index_size = tb_size.div_ceil(d.span as u64) as usize;
+        let padding = read_u8(bytes, data + 2) as usize;
+        d.num_blocks = read_u32_le(bytes, data + 3) as usize;
+        d.block_length_size = d.num_blocks + padding;
+        d.max_sym_len = read_u8(bytes, data + 7) as usize;
+        d.min_sym_len = read_u8(bytes, data + 8) as usize;
+        data += 9;
+        d.lowest_sym = data;
+
+        // Canonical huffman code: longer symbols have lower values, base64[i] is the lowest
+        // symbol of length min_sym_len + i, left aligned to 64 bits
+        let lens = d.max_sym_len - d.min_sym_len + 1;
+        d.base64 = vec![0; lens];
+        for i in (0..lens - 1).rev() {
+            let lowest = read_u16_le(bytes, d.lowest_sym + 2 * i) as u64;
+            let lowest_next = read_u16_le(bytes, d.lowest_sym + 2 * (i + 1)) as u64;
+            d.base64[i] = (d.base64[i + 1] + lowest - lowest_next) / 2;
+        }
+        for (i, base) in d.base64.iter_mut().enumerate() {
+            *base <<= 64 - i - d.min_sym_len;
+        }
+
+        data += lens * 2;
+        let sym_nb = read_u16_le(bytes, data) as usize;
+        data += 2;
+        d.btree = data;
+        d.symlen = vec![0; sym_nb];
+        let mut visited = vec![false; sym_nb];
+        for sym in 0..sym_nb {
+            if !visited[sym] {
+                d.symlen[sym] = self.set_symlen(d, sym, &mut visited);
+            }
+        }
+
+        data + sym_nb * 3 + (sym_nb & 1)
+    }
+
+    fn decompress_pairs(&self, d: &PairsData, idx: u64) -> i32 {
+        if d.flags & FLAG_SINGLE_VALUE != 0 {
+            return d.min_sym_len as i32;
+        }
+        let bytes = &self.mmap;
+
+        // The sparse index points close to the block containing idx
+        let k = (idx / d.span as u64) as usize;
+        let mut block = read_u32_le(bytes, d.sparse_index + 6 * k) as usize;
+        let mut offset = read_u16_le(bytes, d.sparse_index + 6 * k + 4) as i64;
+        offset += (idx % d.span as u64) as i64 - (d.span / 2) as i64;
+
+        let block_length = |b: usize| read_u16_le(bytes, d.block_length + 2 * b) as i64;
+        while offset < 0 {
+            block -= 1;
+            offset += block_length(block) + 1;
+        }
+        while offset > block_length(block) {
+            offset -= block_length(block) + 1;
+            block += 1;
+        }
+
+        let mut ptr = d.data + block * d.block_size;
+        let mut buf64 = read_u64_be(bytes, ptr);
+        let mut buf64_size = 64;
+        ptr += 8;
+
+        let mut sym;
+        loop {
+            let mut len = 0;
+            while buf64 < d.base64[len] {
+                len += 1;
+            }
+            sym = ((buf64 - d.base64[len]) >> (64 - len - d.min_sym_len)) as usize;
+            sym += read_u16_le(bytes, d.lowest_sym + 2 * len) as usize;
+
+            if offset < d.symlen[sym] as i64 + 1 {
+                break;
+            }
+
+            offset -= d.symlen[sym] as i64 + 1;
+            len += d.min_sym_len;
+            buf64 <<= len;
+            buf64_size -= len;
+            if buf64_size <= 32 {
+                buf64_size += 32;
+                buf64 |= (read_u32_be(bytes, ptr) as u64) << (64 - buf64_size);
+                ptr += 4;
+            }
+        }
+
+        // Expand the symbol until we reach the leaf storing our value
+        while d.symlen[sym] != 0 {
+            let left = self.btree_left(d, sym);
+            if offset < d.symlen[left] as i64 + 1 {
+                sym = left;
+            } else {
+                offset -= d.symlen[left] as i64 + 1;
+                sym = self.btree_right(d, sym);
+            }
+        }
+
+        self.btree_left(d, sym) as i32
+    }
+}
+
+impl Material {
+    fn new(name: &str) -> Option<Material> {
+        let mut sides = name.split('v');
+        let (white, black) = (sides.next()?, sides.next()?);
+        let code = format!("{}{}", white, black);
+        if code.len() > TB_PIECES || code.chars().any(|c| !"KQRBNP".contains(c)) {
+            return None;
+        }
+
+        let count = |s: &str, c: char| s.chars().filter(|&x| x == c).count();
+        let (w_pawns, b_pawns) = (count(white, 'P'), count(black, 'P'));
+        // The side with less pawns leads, as this compresses better
+        let lead_white = b_pawns == 0 || w_pawns > 0 && b_pawns >= w_pawns;
+        let has_unique_pieces = [white, black]
+            .iter()
+            .any(|side| "PNBRQ".chars().any(|c| count(side, c) == 1));
+
+        Some(Material {
+            key: signature(&code, WHITE),
+            key2: signature(&code, BLACK),
+            piece_count: code.len(),
+            has_pawns: w_pawns + b_pawns > 0,
+            has_unique_pieces,
+            pawn_count: if lead_white {
+                [w_pawns, b_pawns]
+            } else {
+                [b_pawns, w_pawns]
+            },
+        })
+    }
+
+    fn set_groups(&self, d: &mut PairsData, order: [u8; 2], f: usize) {
+        let mut n = 0;
+        let mut first_len: i32 = if self.has_pawns {
+            0
+        } else if self.has_unique_pieces {
+            3
+        } else {
+            2
+        };
+        d.group_len[n] = 1;
+
+        // Pieces of the same kind form a group, the leading group might be larger
+        for i in 1..self.piece_count {
+            first_len -= 1;
+            if first_len > 0 || d.pieces[i] == d.pieces[i - 1] {
+                d.group_len[n] += 1;
+            } else {
+                n += 1;
+                d.group_len[n] = 1;
+            }
+        }
+        n += 1;
+        d.group_len[n] = 0;
+
+        // The groups are encoded in the order given by the table
+        let pp = self.has_pawns && self.pawn_count[1] > 0;
+        let mut next = if pp { 2 } else { 1 };
+        let mut free_squares = 64 - d.group_len[0] - if pp { d.group_len[1] } else { 0 };
+        let mut idx = 1u64;
+
+        let mut k = 0;
+        while next < n || k == order[0] as usize || k == order[1] as usize {
+            if k == order[0] as usize {
+                d.group_idx[0] = idx;
+                idx *= if self.has_pawns {
+                    LEAD_PAWNS.1[d.group_len[0]][f]
+                } else if self.has_unique_pieces {
+                    31332
+                } else {
+                    462
+                };
+            } else if k == order[1] as usize {
+                d.group_idx[1] = idx;
+                idx *= BINOMIAL[d.group_len[1]][48 - d.group_len[0]];
+            } else {
+                d.group_idx[next] = idx;
+                idx *= BINOMIAL[d.group_len[next]][free_squares];
+                free_squares -= d.group_len[next];
+                next += 1;
+            }
+            k += 1;
+        }
+        d.group_idx[n] = idx;
+    }
+}
+
+impl Table {
+    fn probe(&self, pos: &Position, is_dtz: bool, wdl: Wdl) -> (ProbeState, i32) {
+        let mat = &self.mat;
+        let td = match (is_dtz, &self.dtz) {
+            (false, _) => &self.wdl,
+            (true, Some(dtz)) => dtz,
+            (true, None) => return (ProbeState::Fail, 0),
+        };
+
+        let mut squares = [0 as Square; TB_PIECES];
+        let mut pieces = [0 as Piece; TB_PIECES];
+        let mut size = 0;
+        let mut lead_pawns = BB_ZERO;
+        let mut lead_pawns_cnt = 0;
+        let mut tb_file = FILE_A;
+
+        // Tables are stored with white as the stronger side, and symmetric tables only with
+        // white to move. Otherwise we have to flip colors and squares.
+        let symmetric_btm = mat.key == mat.key2 && pos.ctm == BLACK;
+        let black_stronger = pos.material_key() != mat.key;
+        let flip = symmetric_btm || black_stronger;
+        let flip_color = if flip { 8 } else { 0 };
+        let flip_squares = if flip { 56 } else { 0 };
+        let stm = (flip as Color ^ pos.ctm) as usize;
+
+        let pawn_order = |sq: &Square| MAP_PAWNS[*sq as usize];
+        if mat.has_pawns {
+            let pc = td.items[0][0].pieces[0] ^ flip_color;
+            lead_pawns = pos.piece_bb(PAWN, color_of(pc));
+            for sq in lead_pawns {
+                squares[size] = sq ^ flip_squares;
+                size += 1;
+            }
+            lead_pawns_cnt = size;
+            let lead = (0..lead_pawns_cnt)
+                .rev()
+                .max_by_key(|&i| pawn_order(&squares[i]))
+                .unwrap();
+            squares.swap(0, lead);
+            let file = file_of(squares[0]);
+            tb_file = file.min(FILE_H - file);
+        }
+
+        // DTZ tables only store one side to move
+        let side = if is_dtz { 0 } else { stm % td.items.len() };
+        let d = &td.items[side][tb_file];
+        if is_dtz && (d.flags & FLAG_STM) as usize != stm && (mat.key != mat.key2 || mat.has_pawns)
+        {
+            return (ProbeState::ChangeStm, 0);
+        }
+
+        for sq in pos.piecetype_bb(ALL) & !lead_pawns {
+            squares[size] = sq ^ flip_squares;
+            pieces[size] = pos.piece_on(sq).unwrap() ^ flip_color;
+            size += 1;
+        }
+
+        // Reorder the pieces to the sequence stored in the table
+        for i in lead_pawns_cnt..size - 1 {
+            for j in i + 1..size {
+                if d.pieces[i] == pieces[j] {
+                    pieces.swap(i, j);
+                    squares.swap(i, j);
+                    break;
+                }
+            }
+        }
+
+        // The leading piece has to be on files A-D
+        if file_of(squares[0]) > FILE_D {
+            for sq in squares[..size].iter_mut() {
+                *sq = flip_file(*sq);
+            }
+        }
+
+        let mut idx;
+        if mat.has_pawns {
+            idx = LEAD_PAWNS.0[lead_pawns_cnt][squares[0] as usize];
+            squares[1..lead_pawns_cnt].sort_by_key(pawn_order);
+            for i in 1..lead_pawns_cnt {
+                idx += BINOMIAL[i][MAP_PAWNS[squares[i] as usize]];
+            }
+        } else {
+            // The leading piece has to be below rank 5 and on or below the a1-h8 diagonal
+            if rank_of(squares[0]) > RANK_4 {
+                for sq in squares[..size].iter_mut() {
+                    *sq = flip_rank(*sq);
+                }
+            }
+            for i in 0..d.group_len[0] {
+                if off_a1h8(squares[i]) == 0 {
+                    continue;
+                }
+                if off_a1h8(squares[i]) > 0 {
+                    for sq in squares[i..size].iter_mut() {
+                        *sq = ((*sq >> 3) | (*sq << 3)) & 63;
+                    }
+                }
+                break;
+            }
+
+            let s = |i: usize| squares[i] as u64;
+            let r = |i: usize| rank_of(squares[i]) as u64;
+            if mat.has_unique_pieces {
+                let adjust1 = (squares[1] > squares[0]) as u64;
+                let adjust2 = (squares[2] > squares[0]) as u64 + (squares[2] > squares[1]) as u64;
+                idx = if off_a1h8(squares[0]) != 0 {
+                    (MAP_A1D1D4[squares[0] as usize] as u64 * 63 + (s(1) - adjust1)) * 62 + s(2)
+                        - adjust2
+                } else if off_a1h8(squares[1]) != 0 {
+                    (6 * 63 + r(0) * 28 + MAP_B1H1H7[squares[1] as usize] as u64) * 62 + s(2)
+                        - adjust2
+                } else if off_a1h8(squares[2]) != 0 {
+                    6 * 63 * 62
+                        + 4 * 28 * 62
+                        + r(0) * 7 * 28
+                        + (r(1) - adjust1) * 28
+                        + MAP_B1H1H7[squares[2] as usize] as u64
+                } else {
+                    6 * 63 * 62
+                        + 4 * 28 * 62
+                        + 4 * 7 * 28
+                        + r(0) * 7 * 6
+                        + (r(1) - adjust1) * 6
+                        + (r(2) - adjust2)
+                };
+            } else {
+                idx = MAP_KK[MAP_A1D1D4[squares[0] as usize]][squares[1] as usize] as u64;
+            }
+        }
+
+        // Encode the remaining groups, each in ascending order of squares
+        idx *= d.group_idx[0];
+        let mut group_start = d.group_len[0];
+        let mut remaining_pawns = mat.has_pawns && mat.pawn_count[1] > 0;
+        let mut next = 1;
+        while d.group_len[next] != 0 {
+            let group_end = group_start + d.group_len[next];
+            squares[group_start..group_end].sort_unstable();
+            let mut n = 0;
+            for i in 0..d.group_len[next] {
+                let sq = squares[group_start + i];
+                let adjust = squares[..group_start].iter().filter(|&&x| sq > x).count();
+                n += BINOMIAL[i + 1][sq as usize - adjust - 8 * remaining_pawns as usize];
+            }
+            remaining_pawns = false;
+            idx += n * d.group_idx[next];
+            group_start = group_end;
+            next += 1;
+        }
+
+        let value = td.decompress_pairs(d, idx);
+        if !is_dtz {
+            return (ProbeState::Ok, value - 2);
+        }
+
+        // DTZ values might be mapped and stored in moves instead of plies
+        let mut value = value as usize;
+        if d.flags & FLAG_MAPPED != 0 {
+            let map_idx = d.map_idx[[1, 3, 0, 2, 0][(wdl + 2) as usize]];
+            value = if d.flags & FLAG_WIDE != 0 {
+                read_u16_le(&td.mmap, td.map + 2 * (map_idx + value)) as usize
+            } else {
+                read_u8(&td.mmap, td.map + map_idx + value) as usize
+            };
+        }
+        if wdl == WDL_WIN && d.flags & FLAG_WIN_PLIES == 0
+            || wdl == WDL_LOSS && d.flags & FLAG_LOSS_PLIES == 0
+            || wdl == WDL_CURSED_WIN
+            || wdl == WDL_BLESSED_LOSS
+        {
+            value *= 2;
+        }
+        (ProbeState::Ok, value as i32 + 1)
+    }
+}
+
+fn map_file(path: &Path) -> Option<Mmap> {
+    let file = File::open(path).ok()?;
+    unsafe { Mmap::map(&file).ok() }
+}
+
+fn is_zeroing(pos: &Position, mv: Move) -> bool {
+    pos.is_capture(mv) || piecetype_of(pos.piece_on(mv.from()).unwrap()) == PAWN
+}
+
+fn dtz_before_zeroing(wdl: Wdl) -> i32 {
+    match wdl {
+        WDL_WIN => 1,
+        WDL_CURSED_WIN => 101,
+        WDL_BLESSED_LOSS => -101,
+        WDL_LOSS => -1,
+        _ => 0,
+    }
+}
+
+impl Tablebases {
+    // Paths are separated by ':' on unix and ';' on windows
+    pub fn init(paths: &str) -> Tablebases {
+        let mut tb = Tablebases::default();
+        let sep = if cfg!(windows) { ';' } else { ':' };
+        let dirs = paths
+            .split(sep)
+            .filter(|x| !x.is_empty() && *x != "<empty>")
+            .map(Path::new)
+            .collect::<Vec<&Path>>();
+
+        for dir in dirs.iter() {
+            let entries = match std::fs::read_dir(dir) {
+                Ok(entries) => entries,
+                Err(_) => continue,
+            };
+            for path in entries.filter_map(|x| x.ok()).map(|x| x.path()) {
+                if path.extension().and_then(|x| x.to_str()) != Some("rtbw") {
+                    continue;
+                }
+                let name = path.file_stem().and_then(|x| x.to_str()).unwrap_or("");
+                let dtz = dirs
+                    .iter()
+                    .find_map(|dir| map_file(&dir.join(format!("{}.rtbz", name))));
+                let mat = match Material::new(name) {
+                    Some(mat) if !tb.keys.contains_key(&mat.key) => mat,
+                    _ => continue,
+                };
+                let wdl = match map_file(&path).and_then(|x| TableData::new(x, &mat, false)) {
+                    Some(wdl) => wdl,
+                    None => continue,
+                };
+                let dtz = dtz.and_then(|x| TableData::new(x, &mat, true));
+                tb.max_pieces = tb.max_pieces.max(mat.piece_count as u32);
+                tb.keys.insert(mat.key, tb.tables.len());
+                tb.keys.insert(mat.key2, tb.tables.len());
+                tb.tables.push(Table { mat, wdl, dtz });
+            }
+        }
+        tb
+    }
+
+    pub fn len(&self) -> usize {
+        self.tables.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tables.is_empty()
+    }
+
+    pub fn can_probe(&self, pos: &Position) -> bool {
+        pos.variant == Variant::Standard
+            && pos.cr == 0
+            && pos.piecetype_bb(ALL).popcount() <= self.max_pieces
+    }
+
+    fn probe_table(&self, pos: &Position, is_dtz: bool, wdl: Wdl) -> (ProbeState, i32) {
+        if pos.piecetype_bb(ALL).popcount() == 2 {
+            return (ProbeState::Ok, WDL_DRAW);
+        }
+        match self.keys.get(&pos.material_key()) {
+            Some(&idx) => self.tables[idx].probe(pos, is_dtz, wdl),
+            None => (ProbeState::Fail, 0),
+        }
+    }
+
+    // Tables store "don't care" values for positions where a capture (or for DTZ, a pawn move)
+    // is the best move, so these have to be searched
+    fn search(&self, pos: &Position, ci: &CastleInfo, check_zeroing: bool) -> (ProbeState, Wdl) {
+        let moves = pos.gen_legals(ci);
+        let legal_count = moves.len();
+        let mut best = WDL_LOSS;
+        let mut move_count = 0;
+
+        for mv in moves {
+            if !pos.is_capture(mv) && (!check_zeroing || !is_zeroing(pos, mv)) {
+                continue;
+            }
+            move_count += 1;
+            let mut new_pos = pos.clone();
+            new_pos.make_move(mv, ci);
+            let (state, value) = self.search(&new_pos, ci, false);
+            if state == ProbeState::Fail {
+                return (ProbeState::Fail, WDL_DRAW);
+            }
+            if -value > best {
+                best = -value;
+                if best >= WDL_WIN {
+                    return (ProbeState::ZeroingBestMove, best);
+                }
+            }
+        }
+
+        let no_more_moves = move_count > 0 && move_count == legal_count;
+        let value = if no_more_moves {
+            best
+        } else {
+            match self.probe_table(pos, false, WDL_DRAW) {
+                (ProbeState::Fail, _) => return (ProbeState::Fail, WDL_DRAW),
+                (_, value) => value,
+            }
+        };
+
+        if best >= value {
+            let zeroing = best > WDL_DRAW || no_more_moves;
+            let state = [ProbeState::Ok, ProbeState::ZeroingBestMove][zeroing as usize];
+            return (state, best);
+        }
+        (ProbeState::Ok, value)
+    }
+
+    pub fn probe_wdl(&self, pos: &Position, ci: &CastleInfo) -> Option<Wdl> {
+        match self.search(pos, ci, false) {
+            (ProbeState::Fail, _) => None,
+            (_, wdl) => Some(wdl),
+        }
+    }
+
+    pub fn probe_dtz(&self, pos: &Position, ci: &CastleInfo) -> Option<i32> {
+        let (state, wdl) = self.search(pos, ci, true);
+        if state == ProbeState::Fail {
+            return None;
+        }
+        if wdl == WDL_DRAW {
+            return Some(0);
+        }
+        if state == ProbeState::ZeroingBestMove {
+            return Some(dtz_before_zeroing(wdl));
+        }
+
+        let (state, dtz) = self.probe_table(pos, true, wdl);
+        match state {
+            ProbeState::Fail => return None,
+            ProbeState::ChangeStm => {}
+            _ => {
+                let cursed = (wdl == WDL_BLESSED_LOSS || wdl == WDL_CURSED_WIN) as i32;
+                return Some((dtz + 100 * cursed) * wdl.signum());
+            }
+        }
+
+        // The table stores the other side to move, do a 1-ply search for the best DTZ
+        let mut min_dtz = 0xFFFF;
+        for mv in pos.gen_legals(ci) {
+            let mut new_pos = pos.clone();
+            new_pos.make_move(mv, ci);
+            let zeroing = is_zeroing(pos, mv);
+            let mut dtz = if zeroing {
+                -dtz_before_zeroing(self.probe_wdl(&new_pos, ci)?)
+            } else {
+                -self.probe_dtz(&new_pos, ci)?
+            };
+            if dtz == 1 && new_pos.in_check(new_pos.ctm) && new_pos.gen_legals(ci).is_empty() {
+                min_dtz = 1;
+            }
+            if !zeroing {
+                dtz += dtz.signum();
+            }
+            if dtz < min_dtz && dtz.signum() == wdl.signum() {
+                min_dtz = dtz;
+            }
+        }
+        Some(if min_dtz == 0xFFFF { -1 } else { min_dtz })
+    }
+
+    // Returns the root moves which preserve the best DTZ rank, or None if probing failed
+    pub fn root_probe(&self, pos: &Position, ci: &CastleInfo) -> Option<Vec<Move>> {
+        let mr50 = pos.mr50 as i32;
+        let mut ranked = Vec::new();
+        for mv in pos.gen_legals(ci) {
+            let mut new_pos = pos.clone();
+            new_pos.make_move(mv, ci);
+            let mut dtz = if new_pos.mr50 == 0 {
+                dtz_before_zeroing(-self.probe_wdl(&new_pos, ci)?)
+            } else {
+                let dtz = -self.probe_dtz(&new_pos, ci)?;
+                dtz + dtz.signum()
+            };
+            if new_pos.in_check(new_pos.ctm) && dtz == 2 && new_pos.gen_legals(ci).is_empty() {
+                dtz = 1;
+            }
+
+            // Wins within the 50 move rule are ranked equally, losses are ranked equally unless
+            // a 50 move draw is in sight
+            let rank = if dtz > 0 {
+                if dtz + mr50 <= 99 {
+                    1000
+                } else {
+                    1000 - (dtz + mr50)
+                }
+            } else if dtz < 0 {
+                if -dtz * 2 + mr50 < 100 {
+                    -1000
+                } else {
+                    -1000 + (-dtz + mr50)
+                }
+            } else {
+                0
+            };
+            ranked.push((mv, rank));
+        }
+
+        let best = ranked.iter().map(|x| x.1).max()?;
+        Some(
+            ranked
+                .into_iter()
+                .filter(|x| x.1 == best)
+                .map(|x| x.0)
+                .collect(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Small 3 piece tables in the Syzygy format, checked into the repository
+    const FIXTURES: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/syzygy");
+
+    fn probe(tb: &Tablebases, fen: &str) -> (Wdl, i32) {
+        let (pos, ci) = Position::from_fen(fen).unwrap();
+        assert!(tb.can_probe(&pos));
+        (
+            tb.probe_wdl(&pos, &ci).unwrap(),
+            tb.probe_dtz(&pos, &ci).unwrap(),
+        )
+    }
+
+    #[test]
+    fn wdl_and_dtz() {
+        let tb = Tablebases::init(FIXTURES);
+        assert_eq!(tb.len(), 3);
+        // KQvK and KRvK have no zeroing moves, DTZ is the distance to mate
+        assert_eq!(probe(&tb, "k7/8/8/8/8/8/8/KQ6 w - - 0 1"), (WDL_WIN, 13));
+        assert_eq!(probe(&tb, "k7/8/8/8/8/8/8/KQ6 b - - 0 1"), (WDL_LOSS, -14));
+        assert_eq!(probe(&tb, "8/8/8/4k3/8/8/8/R3K3 w - - 0 1"), (WDL_WIN, 27));
+        assert_eq!(
+            probe(&tb, "8/8/8/4k3/8/8/8/R3K3 b - - 0 1"),
+            (WDL_LOSS, -28)
+        );
+        assert_eq!(probe(&tb, "k7/8/1K6/8/8/8/7Q/8 w - - 0 1"), (WDL_WIN, 1));
+        // KRvK where black captures the hanging rook
+        assert_eq!(probe(&tb, "8/8/8/8/8/8/3kR3/6K1 b - - 0 1"), (WDL_DRAW, 0));
+        // KPvK: promoting is a zeroing win, the king in front of the pawn on the sixth wins
+        assert_eq!(probe(&tb, "8/4P3/8/8/8/8/k7/4K3 w - - 0 1"), (WDL_WIN, 1));
+        assert_eq!(
+            probe(&tb, "4k3/8/4K3/4P3/8/8/8/8 b - - 0 1"),
+            (WDL_LOSS, -4)
+        );
+        // Stalemate with black to move, white to move walks around with Kf6-f7
+        assert_eq!(probe(&tb, "4k3/4P3/4K3/8/8/8/8/8 b - - 0 1"), (WDL_DRAW, 0));
+        assert_eq!(probe(&tb, "4k3/4P3/4K3/8/8/8/8/8 w - - 0 1"), (WDL_WIN, 5));
+        // Black pawns are probed through the color flipped table
+        assert_eq!(probe(&tb, "8/8/8/8/8/1k6/7p/7K b - - 0 1"), (WDL_DRAW, 0));
+    }
+
+    #[test]
+    fn kpk_matches_bitbase() {
+        let tb = Tablebases::init(FIXTURES);
+        let ci = CastleInfo::default();
+        for pawn in A2..=H7 {
+            for wk in A1..=H8 {
+                for bk in A1..=H8 {
+                    if pawn == wk || pawn == bk || wk == bk || distance(wk, bk) <= 1 {
+                        continue;
+                    }
+                    for &ctm in [WHITE, BLACK].iter() {
+                        let pieces = [(W_PAWN, pawn), (W_KING, wk), (B_KING, bk)];
+                        let pos = Position::from_pieces(&pieces, ctm);
+                        if pos.in_check(swap_color(ctm)) {
+                            continue;
+                        }
+                        let expected = crate::bitbase::probe_kpk(&pos).unwrap().signum() as Wdl;
+                        assert_eq!(tb.probe_wdl(&pos, &ci), Some(2 * expected));
+                    }
+                }
+            }
+        }
+    }
+}
@@ -1,8 +1,12 @@
+use crate::book::Book;
+use crate::dtm::DtmTables;
 use crate::history::HashHist;
 use crate::position::{CastleInfo, Position};
 use crate::r#move::*;
 use crate::search::{start_search, Limits};
+use crate::syzygy::Tablebases;
 use crate::transposition::{DEFAULT_TT_SIZE, TT};
+use crate::types::Score;
 
 use std::cell::UnsafeCell;
 use std::sync::atomic::{AtomicBool, Ordering};
@@ -13,9 +17,10 @@ use std::thread;
 pub struct UnsafePtr<T>(*mut T);
 unsafe impl<T> Send for UnsafePtr<T> {}
 
+// Searched nodes and tablebase hits of a single thread
 #[repr(C, align(64))]
 #[derive(Clone, Copy, Default)]
-pub struct Node(u64);
+pub struct Node(u64, u64);
 
 /*
 Memory model of SharedState and Threads
@@ -32,10 +37,15 @@ pub struct SharedState {
     node_counts: Arc<UnsafeCell<Vec<Node>>>,
     pub abort: Arc<AtomicBool>,
     pub tt: Arc<UnsafeCell<TT>>,
+    pub tb: Arc<Tablebases>,
+    pub dtm: Arc<DtmTables>,
+    pub book: Option<Book>,
+    pub own_book: bool,
     txs: Vec<Sender<Option<Thread>>>,
 }
 
 impl Default for SharedState {
+    #[allow(clippy::arc_with_non_send_sync)]
     fn default() -> Self {
         let mut tt = TT::default();
         tt.allocate(DEFAULT_TT_SIZE);
@@ -43,6 +53,10 @@ impl Default for SharedState {
             node_counts: Arc::new(UnsafeCell::new(Vec::new())),
             abort: Arc::new(AtomicBool::new(false)),
             tt: Arc::new(UnsafeCell::new(tt)),
+            tb: Arc::new(Tablebases::default()),
+            dtm: Arc::new(DtmTables::default()),
+            book: None,
+            own_book: false,
             txs: Vec::new(),
         }
     }
@@ -52,7 +66,7 @@ impl SharedState {
     pub fn reset_nodes(&self) {
         unsafe {
             for i in 0..self.txs.len() {
-                self.node_counts.get().as_mut().unwrap()[i].0 = 0;
+                self.node_counts.get().as_mut().unwrap()[i] = Node::default();
             }
         }
     }
@@ -60,11 +74,48 @@ impl SharedState {
     pub fn reallocate_tt(&mut self, size_in_mb: usize) {
         unsafe { self.tt.get().as_mut().unwrap().allocate(size_in_mb) }
     }
+
+    pub fn clear_tt(&mut self) {
+        unsafe { self.tt.get().as_mut().unwrap().clear() }
+    }
+
+    pub fn load_tablebases(&mut self, paths: &str) {
+        self.tb = Arc::new(Tablebases::init(paths));
+        if !self.tb.is_empty() {
+            println!(
+                "info string Found {} tablebases with up to {} pieces",
+                self.tb.len(),
+                self.tb.max_pieces
+            );
+        }
+    }
+
+    pub fn load_dtm_tables(&mut self, paths: &str) {
+        self.dtm = Arc::new(DtmTables::init(paths));
+        if !self.dtm.is_empty() {
+            println!("info string Found {} DTM tables", self.dtm.len());
+        }
+    }
+
+    pub fn load_book(&mut self, path: &str) {
+        self.book = None;
+        if path.is_empty() || path == "<empty>" {
+            return;
+        }
+        match Book::open(path) {
+            Ok(book) => {
+                println!("info string Found {} book entries", book.len());
+                self.book = Some(book);
+            }
+            Err(err) => println!("info string Failed to open book {}: {}", path, err),
+        }
+    }
+
     pub fn launch_threads(&mut self, threads: usize) {
         self.txs.iter().for_each(|x| {
             x.send(None).unwrap();
         });
-        unsafe { *self.node_counts.get().as_mut().unwrap() = vec![Node(0); threads] };
+        unsafe { *self.node_counts.get().as_mut().unwrap() = vec![Node::default(); threads] };
         self.txs = Vec::new();
         for _ in 0..threads {
             let (tx, rx) = channel();
@@ -98,12 +149,16 @@ pub struct Thread {
     pub nodes: UnsafePtr<Node>,
     pub abort: Arc<AtomicBool>,
     pub tt: Arc<UnsafeCell<TT>>,
+    pub tb: Arc<Tablebases>,
+    pub dtm: Arc<DtmTables>,
     pub limits: Limits,
 
     pub root: Position,
     pub ci: CastleInfo,
     pub hist: HashHist,
     pub best_move: Move,
+    pub best_score: Score,
+    pub root_moves: Vec<Move>,
 }
 unsafe impl Send for Thread {}
 
@@ -116,14 +171,16 @@ impl Thread {
         unsafe {
             let ptr = shared_state.node_counts.get().as_mut().unwrap();
             let nodes = UnsafePtr(ptr.as_mut_ptr().add(id));
-            let tt = shared_state.tt.clone();
+            let (tt, tb) = (shared_state.tt.clone(), shared_state.tb.clone());
+            let dtm = shared_state.dtm.clone();
             let (node_counts, abort) =
                 (shared_state.node_counts.clone(), shared_state.abort.clone());
-            let best_move = NO_MOVE;
+            let (best_move, best_score) = (NO_MOVE, 0);
+            let root_moves = Vec::new();
 
             Thread {
-                id, nodes, tt, node_counts, root, ci,
-                best_move,limits, abort, hist
+                id, nodes, tt, tb, dtm, node_counts, root, ci,
+                best_move, best_score, limits, abort, hist, root_moves
             }
         }
     }
@@ -136,6 +193,21 @@ impl Thread {
         unsafe { (*self.nodes.0).0 }
     }
 
+    pub fn inc_tbhits(&self) {
+        unsafe { (*self.nodes.0).1 += 1 };
+    }
+
+    pub fn get_global_tbhits(&self) -> u64 {
+        unsafe {
+            self.node_counts
+                .get()
+                .as_ref()
+                .unwrap()
+                .iter()
+                .fold(0, |acc, x| acc + x.1)
+        }
+    }
+
     pub fn get_global_nodes(&self) -> u64 {
         unsafe {
             self.node_counts
@@ -0,0 +1,633 @@
+use crate::epd::Epd;
+use crate::pgn::{Game, PgnReader};
+use crate::position::{CastleInfo, Position};
+use crate::r#move::*;
+use crate::types::*;
+
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc::{channel, Receiver};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/*
+Plays a match between two UCI engines. Every opening is played twice with the colors swapped.
+Elo and its 95% error bars follow from the mean and variance of the game scores, the SPRT uses
+the normal approximation of the log-likelihood ratio for the logistic Elo model:
+  LLR = N * (s1 - s0) * (2 * s - s0 - s1) / (2 * var)
+with s0 and s1 the expected scores under elo0 and elo1, s the mean score and var its variance.
+*/
+
+const DEFAULT_GAMES: usize = 100;
+const DEFAULT_TC: &str = "10+0.1";
+const DEFAULT_ALPHA: f64 = 0.05;
+const DEFAULT_BETA: f64 = 0.05;
+const STARTUP_TIMEOUT: Duration = Duration::from_secs(10);
+const TIME_MARGIN: u128 = 100;
+const MAX_PLIES: usize = 1000;
+
+// Adjudicate a win when both engines agree on a score beyond the margin for this many plies,
+// and a draw after this many plies of scores within the margin past the minimum ply
+const RESIGN_PLIES: usize = 6;
+const RESIGN_SCORE: i32 = 1000;
+const DRAW_PLIES: usize = 10;
+const DRAW_SCORE: i32 = 10;
+const DRAW_MIN_PLY: usize = 80;
+
+#[derive(Clone, Copy)]
+struct TimeControl {
+    base: u128,
+    inc: u128,
+}
+
+impl TimeControl {
+    // Seconds as <base>+<increment>, e.g. 10+0.1
+    fn parse(s: &str) -> Option<TimeControl> {
+        let mut parts = s.splitn(2, '+');
+        let base = parts.next()?.parse::<f64>().ok()?;
+        let inc = parts.next().unwrap_or("0").parse::<f64>().ok()?;
+        Some(TimeControl {
+            base: (base * 1000.0) as u128,
+            inc: (inc * 1000.0) as u128,
+        })
+    }
+}
+
+struct Engine {
+    path: String,
+    name: String,
+    child: Child,
+    stdin: ChildStdin,
+    rx: Receiver<String>,
+}
+
+impl Engine {
+    fn start(path: &str) -> io::Result<Engine> {
+        let mut child = Command::new(path)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()?;
+        let stdin = child.stdin.take().unwrap();
+        let stdout = child.stdout.take().unwrap();
+
+        // Lines are read on a separate thread so that waiting for them can time out
+        let (tx, rx) = channel();
+        thread::spawn(move || {
+            for line in BufReader::new(stdout).lines().map_while(Result::ok) {
+                if tx.send(line).is_err() {
+                    break;
+                }
+            }
+        });
+
+        let mut engine = Engine {
+            path: path.to_owned(),
+            name: path.to_owned(),
+            child,
+            stdin,
+            rx,
+        };
+        engine.send("uci");
+        let mut name = None;
+        engine
+            .read_until("uciok", STARTUP_TIMEOUT, |line| {
+                if let Some(x) = line.strip_prefix("id name ") {
+                    name = Some(x.trim().to_owned());
+                }
+            })
+            .ok_or_else(|| io::Error::new(io::ErrorKind::TimedOut, "no uciok"))?;
+        if let Some(name) = name {
+            engine.name = name;
+        }
+        Ok(engine)
+    }
+
+    fn restart(&mut self) -> io::Result<()> {
+        *self = Engine::start(&self.path)?;
+        Ok(())
+    }
+
+    fn send(&mut self, cmd: &str) {
+        let _ = writeln!(self.stdin, "{}", cmd).and_then(|_| self.stdin.flush());
+    }
+
+    // Reads lines until one starts with the token, None on timeout or if the engine exited
+    fn read_until(
+        &mut self,
+        token: &str,
+        timeout: Duration,
+        mut f: impl FnMut(&str),
+    ) -> Option<String> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            let left = deadline.saturating_duration_since(Instant::now());
+            let line = self.rx.recv_timeout(left).ok()?;
+            if line.split_whitespace().next() == Some(token) {
+                return Some(line);
+            }
+            f(&line);
+        }
+    }
+
+    fn is_ready(&mut self) -> bool {
+        self.send("isready");
+        self.read_until("readyok", STARTUP_TIMEOUT, |_| {})
+            .is_some()
+    }
+}
+
+impl Drop for Engine {
+    fn drop(&mut self) {
+        self.send("quit");
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+// Wins, draws and losses of the first engine
+#[derive(Clone, Copy, Default)]
+struct Stats {
+    wins: usize,
+    draws: usize,
+    losses: usize,
+}
+
+fn elo(score: f64) -> f64 {
+    -400.0 * (1.0 / score.clamp(1e-6, 1.0 - 1e-6) - 1.0).log10()
+}
+
+fn expected_score(elo: f64) -> f64 {
+    1.0 / (1.0 + 10f64.powf(-elo / 400.0))
+}
+
+impl Stats {
+    fn games(&self) -> usize {
+        self.wins + self.draws + self.losses
+    }
+
+    // Mean and variance of the game scores
+    fn score(&self) -> (f64, f64) {
+        let n = self.games().max(1) as f64;
+        let (w, d, l) = (self.wins as f64, self.draws as f64, self.losses as f64);
+        let mean = (w + d / 2.0) / n;
+        let var = (w * (1.0 - mean).powi(2) + d * (0.5 - mean).powi(2) + l * mean.powi(2)) / n;
+        (mean, var)
+    }
+
+    // Elo difference and the half width of its 95% confidence interval
+    fn elo(&self) -> (f64, f64) {
+        let (mean, var) = self.score();
+        let margin = 1.96 * (var / self.games().max(1) as f64).sqrt();
+        let (low, high) = (elo(mean - margin), elo(mean + margin));
+        (elo(mean), (high - low) / 2.0)
+    }
+
+    fn llr(&self, elo0: f64, elo1: f64) -> f64 {
+        let (mean, var) = self.score();
+        if var == 0.0 {
+            return 0.0;
+        }
+        let (s0, s1) = (expected_score(elo0), expected_score(elo1));
+        self.games() as f64 * (s1 - s0) * (2.0 * mean - s0 - s1) / (2.0 * var)
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Sprt {
+    elo0: f64,
+    elo1: f64,
+    alpha: f64,
+    beta: f64,
+}
+
+impl Sprt {
+    fn bounds(&self) -> (f64, f64) {
+        (
+            (self.beta / (1.0 - self.alpha)).ln(),
+            ((1.0 - self.beta) / self.alpha).ln(),
+        )
+    }
+}
+
+fn load_openings(path: &str) -> Result<Vec<(Position, CastleInfo)>, String> {
+    let file = File::open(path).map_err(|err| format!("Failed to read {}: {}", path, err))?;
+    if path.ends_with(".pgn") {
+        return PgnReader::new(BufReader::new(file))
+            .map(|game| {
+                let game = game.map_err(|err| format!("Invalid opening: {}", err))?;
+                let last = *game.mainline().last().unwrap();
+                Ok((game.nodes[last].pos.clone(), game.ci))
+            })
+            .collect();
+    }
+    BufReader::new(file)
+        .lines()
+        .map_while(Result::ok)
+        .filter(|x| !x.trim().is_empty() && !x.starts_with('#'))
+        .map(|line| match Position::from_fen(&line) {
+            Ok(x) => Ok(x),
+            Err(_) => Epd::parse(&line)
+                .map(|epd| (epd.pos, epd.ci))
+                .map_err(|err| format!("Invalid opening '{}': {}", line, err)),
+        })
+        .collect()
+}
+
+// Score in centipawns from the engine's view, mates count as large scores
+fn parse_score(line: &str) -> Option<i32> {
+    let mut tokens = line
+        .split_whitespace()
+        .skip_while(|&x| x != "score")
+        .skip(1);
+    let (kind, value) = (tokens.next()?, tokens.next()?.parse::<i32>().ok()?);
+    match kind {
+        "cp" => Some(value),
+        "mate" => Some(value.signum() * MATE as i32),
+        _ => None,
+    }
+}
+
+// Plays a game between the engines, the first one plays white if white is 0
+fn play_game(
+    engines: &mut [Engine; 2],
+    white: usize,
+    opening: &(Position, CastleInfo),
+    tc: TimeControl,
+) -> (Game, &'static str, &'static str) {
+    let (mut pos, ci) = opening.clone();
+    let mut game = Game::new(pos.clone(), ci.clone());
+    let start_fen = pos.to_fen(&ci);
+    let engine_idx = |c: Color| if c == WHITE { white } else { 1 - white };
+    let loss = |c: Color| if c == WHITE { "0-1" } else { "1-0" };
+
+    for c in [WHITE, BLACK].iter().copied() {
+        let engine = &mut engines[engine_idx(c)];
+        engine.send(&format!("setoption name UCI_Chess960 value {}", ci.frc));
+        engine.send("ucinewgame");
+        if !engine.is_ready() {
+            return (game, loss(c), "engine not responding");
+        }
+    }
+
+    let mut moves = Vec::new();
+    let mut hashes = vec![pos.hash];
+    let mut white_scores = Vec::new();
+    let mut time = [tc.base; 2];
+    let mut node = 0;
+    let (result, termination) = loop {
+        let legals = pos.gen_legals(&ci).collect::<Vec<Move>>();
+        let repetitions = hashes.iter().filter(|&&x| x == pos.hash).count();
+        if legals.is_empty() {
+            break if pos.in_check(pos.ctm) {
+                (loss(pos.ctm), "checkmate")
+            } else {
+                ("1/2-1/2", "stalemate")
+            };
+        } else if pos.mr50 >= 100 {
+            break ("1/2-1/2", "fifty move rule");
+        } else if repetitions >= 3 {
+            break ("1/2-1/2", "threefold repetition");
+        } else if pos.is_insufficient_material() {
+            break ("1/2-1/2", "insufficient material");
+        } else if moves.len() >= MAX_PLIES {
+            break ("1/2-1/2", "maximum game length");
+        }
+
+        let c = pos.ctm;
+        let engine = &mut engines[engine_idx(c)];
+        let position = if moves.is_empty() {
+            format!("position fen {}", start_fen)
+        } else {
+            format!("position fen {} moves {}", start_fen, moves.join(" "))
+        };
+        engine.send(&position);
+        engine.send(&format!(
+            "go wtime {} btime {} winc {} binc {}",
+            time[WHITE as usize], time[BLACK as usize], tc.inc, tc.inc
+        ));
+
+        let start = Instant::now();
+        let mut score = None;
+        let timeout = Duration::from_millis((time[c as usize] + TIME_MARGIN) as u64);
+        let line = engine.read_until("bestmove", timeout, |line| {
+            score = parse_score(line).or(score);
+        });
+        let elapsed = start.elapsed().as_millis();
+        let line = match line {
+            Some(line) if elapsed <= time[c as usize] + TIME_MARGIN => line,
+            _ => break (loss(c), "time forfeit"),
+        };
+        time[c as usize] = time[c as usize].saturating_sub(elapsed) + tc.inc;
+
+        let mv_str = line.split_whitespace().nth(1).unwrap_or("");
+        let mv = match legals.iter().find(|m| m.to_str(&ci) == mv_str) {
+            Some(&mv) => mv,
+            None => break (loss(c), "illegal move"),
+        };
+        node = game.add_move(node, mv).unwrap();
+        if let Some(score) = score {
+            game.nodes[node].comment = format!("{:+.2}", score as f64 / 100.0);
+        }
+        pos.make_move(mv, &ci);
+        moves.push(mv.to_str(&ci));
+        if pos.mr50 == 0 {
+            hashes.clear();
+        }
+        hashes.push(pos.hash);
+
+        white_scores.push(score.map(|x| if c == WHITE { x } else { -x }));
+        let last = |n: usize| white_scores.iter().rev().take(n).copied();
+        if white_scores.len() >= RESIGN_PLIES {
+            if last(RESIGN_PLIES).all(|x| x.is_some_and(|x| x >= RESIGN_SCORE)) {
+                break ("1-0", "adjudication");
+            }
+            if last(RESIGN_PLIES).all(|x| x.is_some_and(|x| x <= -RESIGN_SCORE)) {
+                break ("0-1", "adjudication");
+            }
+        }
+        if moves.len() >= DRAW_MIN_PLY
+            && last(DRAW_PLIES).all(|x| x.is_some_and(|x| x.abs() <= DRAW_SCORE))
+        {
+            break ("1/2-1/2", "adjudication");
+        }
+    };
+    (game, result, termination)
+}
+
+fn report(stats: &Stats, sprt: Option<Sprt>) -> String {
+    let (elo, margin) = stats.elo();
+    let mut s = format!(
+        "Score {}-{}-{} | Elo {:.1} +/- {:.1}",
+        stats.wins, stats.losses, stats.draws, elo, margin
+    );
+    if let Some(sprt) = sprt {
+        let (lower, upper) = sprt.bounds();
+        s.push_str(&format!(
+            " | LLR {:.2} ({:.2}, {:.2})",
+            stats.llr(sprt.elo0, sprt.elo1),
+            lower,
+            upper
+        ));
+    }
+    s
+}
+
+// Plays an engine match:
+// match <engine1> <engine2> [games <n>] [tc <base+inc>] [openings <file>] [pgn <file>]
+//       [concurrency <n>] [sprt <elo0> <elo1> [<alpha> <beta>]]
+pub fn run_match(args: &[String]) {
+    let usage = "Usage: match <engine1> <engine2> [games <n>] [tc <base+inc>] [openings <file>] \
+                 [pgn <file>] [concurrency <n>] [sprt <elo0> <elo1> [<alpha> <beta>]]";
+    if args.len() < 2 {
+        return println!("{}", usage);
+    }
+    let paths = [args[0].clone(), args[1].clone()];
+    let (mut games, mut concurrency) = (DEFAULT_GAMES, 1);
+    let mut tc = TimeControl::parse(DEFAULT_TC).unwrap();
+    let (mut openings, mut pgn, mut sprt) = (None, None, None);
+
+    let mut tokens = args[2..].iter().peekable();
+    while let Some(token) = tokens.next() {
+        let value = tokens.next().map(|x| x.as_str()).unwrap_or("");
+        match token.as_str() {
+            "games" => games = value.parse().unwrap_or(games),
+            "concurrency" => concurrency = value.parse::<usize>().unwrap_or(1).max(1),
+            "tc" => match TimeControl::parse(value) {
+                Some(x) => tc = x,
+                None => return println!("Invalid time control {}", value),
+            },
+            "openings" => openings = Some(value.to_owned()),
+            "pgn" => pgn = Some(value.to_owned()),
+            "sprt" => {
+                let mut numbers = vec![value.parse::<f64>().ok()];
+                while let Some(x) = tokens.peek().and_then(|x| x.parse::<f64>().ok()) {
+                    numbers.push(Some(x));
+                    tokens.next();
+                }
+                let n = numbers.into_iter().collect::<Option<Vec<f64>>>();
+                sprt = match n.as_deref() {
+                    Some(&[elo0, elo1]) => Some(Sprt {
+                        elo0,
+                        elo1,
+                        alpha: DEFAULT_ALPHA,
+                        beta: DEFAULT_BETA,
+                    }),
+                    Some(&[elo0, elo1, alpha, beta]) => Some(Sprt {
+                        elo0,
+                        elo1,
+                        alpha,
+                        beta,
+                    }),
+                    _ => return println!("{}", usage),
+                };
+            }
+            _ => return println!("{}", usage),
+        }
+    }
+
+    let openings = match openings {
+        Some(path) => match load_openings(&path) {
+            Ok(x) if !x.is_empty() => x,
+            Ok(_) => return println!("No openings in {}", path),
+            Err(err) => return println!("{}", err),
+        },
+        None => vec![Position::startpos()],
+    };
+    let pgn = match pgn {
+        Some(path) => match OpenOptions::new().create(true).append(true).open(&path) {
+            Ok(file) => Some(file),
+            Err(err) => return println!("Failed to open {}: {}", path, err),
+        },
+        None => None,
+    };
+
+    let openings = Arc::new(openings);
+    let pgn = Arc::new(Mutex::new(pgn));
+    let stats = Arc::new(Mutex::new(Stats::default()));
+    let (next_game, stop) = (
+        Arc::new(AtomicUsize::new(0)),
+        Arc::new(AtomicBool::new(false)),
+    );
+    let start = Instant::now();
+
+    let handles = (0..concurrency)
+        .map(|_| {
+            let (paths, openings, pgn) = (paths.clone(), openings.clone(), pgn.clone());
+            let (stats, next_game, stop) = (stats.clone(), next_game.clone(), stop.clone());
+            thread::spawn(move || {
+                let engines = [Engine::start(&paths[0]), Engine::start(&paths[1])];
+                let mut engines = match engines {
+                    [Ok(first), Ok(second)] => [first, second],
+                    [Err(err), _] | [_, Err(err)] => {
+                        stop.store(true, Ordering::Relaxed);
+                        return println!("Failed to start engine: {}", err);
+                    }
+                };
+
+                while !stop.load(Ordering::Relaxed) {
+                    let idx = next_game.fetch_add(1, Ordering::Relaxed);
+                    if idx >= games {
+                        break;
+                    }
+                    let white = idx % 2;
+                    let opening = &openings[(idx / 2) % openings.len()];
+                    let (mut game, result, termination) =
+                        play_game(&mut engines, white, opening, tc);
+
+                    let names = [engines[white].name.clone(), engines[1 - white].name.clone()];
+                    game.set_tag("Event", "Scam match");
+                    game.set_tag("Round", &(idx + 1).to_string());
+                    game.set_tag("White", &names[0]);
+                    game.set_tag("Black", &names[1]);
+                    game.set_tag(
+                        "TimeControl",
+                        &format!("{}+{}", tc.base as f64 / 1000.0, tc.inc as f64 / 1000.0),
+                    );
+                    game.set_tag("Termination", termination);
+                    game.set_result(result);
+                    if let Some(file) = pgn.lock().unwrap().as_mut() {
+                        // to_pgn already ends with the empty line separating games
+                        let _ = write!(file, "{}", game.to_pgn());
+                    }
+
+                    // Engines which failed are restarted for the next game
+                    if ["time forfeit", "engine not responding"].contains(&termination) {
+                        for engine in engines.iter_mut() {
+                            if let Err(err) = engine.restart() {
+                                stop.store(true, Ordering::Relaxed);
+                                return println!("Failed to restart engine: {}", err);
+                            }
+                        }
+                    }
+
+                    let mut stats = stats.lock().unwrap();
+                    match (result, white) {
+                        ("1/2-1/2", _) => stats.draws += 1,
+                        ("1-0", 0) | ("0-1", 1) => stats.wins += 1,
+                        _ => stats.losses += 1,
+                    }
+                    println!(
+                        "Game {} {} vs {}: {} ({}) | {}",
+                        idx + 1,
+                        names[0],
+                        names[1],
+                        result,
+                        termination,
+                        report(&stats, sprt)
+                    );
+                    if let Some(sprt) = sprt {
+                        let (lower, upper) = sprt.bounds();
+                        let llr = stats.llr(sprt.elo0, sprt.elo1);
+                        if llr <= lower || llr >= upper {
+                            stop.store(true, Ordering::Relaxed);
+                        }
+                    }
+                }
+            })
+        })
+        .collect::<Vec<_>>();
+    for handle in handles {
+        let _ = handle.join();
+    }
+
+    let stats = *stats.lock().unwrap();
+    println!(
+        "\n{} games - {} - Time {:.3}",
+        stats.games(),
+        report(&stats, sprt),
+        start.elapsed().as_secs_f64()
+    );
+    if let Some(sprt) = sprt {
+        let (lower, upper) = sprt.bounds();
+        let llr = stats.llr(sprt.elo0, sprt.elo1);
+        let verdict = if llr >= upper {
+            "H1 accepted"
+        } else if llr <= lower {
+            "H0 accepted"
+        } else {
+            "inconclusive"
+        };
+        println!("SPRT [{}, {}]: {}", sprt.elo0, sprt.elo1, verdict);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stats(wins: usize, draws: usize, losses: usize) -> Stats {
+        Stats {
+            wins,
+            draws,
+            losses,
+        }
+    }
+
+    fn assert_close(a: f64, b: f64) {
+        assert!((a - b).abs() < 0.01, "{} != {}", a, b);
+    }
+
+    #[test]
+    fn elo_and_error_bars() {
+        assert_close(elo(0.5), 0.0);
+        assert_close(elo(0.75), 190.85);
+        assert_close(elo(0.25), -190.85);
+        assert_close(expected_score(elo(0.6)), 0.6);
+
+        // Same results as cutechess-cli for these W/D/L counts
+        let (mean, var) = stats(60, 20, 20).score();
+        assert_close(mean, 0.7);
+        assert_close(var, 0.16);
+        let (diff, margin) = stats(60, 20, 20).elo();
+        assert_close(diff, 147.19);
+        assert_close(margin, 66.01);
+        let (diff, margin) = stats(30, 40, 30).elo();
+        assert_close(diff, 0.0);
+        assert_close(margin, 53.16);
+        let (diff, margin) = stats(100, 300, 80).elo();
+        assert_close(diff, 14.48);
+        assert_close(margin, 19.04);
+    }
+
+    #[test]
+    fn sprt() {
+        let sprt = Sprt {
+            elo0: 0.0,
+            elo1: 5.0,
+            alpha: 0.05,
+            beta: 0.05,
+        };
+        let (lower, upper) = sprt.bounds();
+        assert_close(lower, -2.944);
+        assert_close(upper, 2.944);
+
+        let llr = |s: Stats| s.llr(sprt.elo0, sprt.elo1);
+        assert_close(llr(stats(60, 20, 20)), 0.883);
+        assert_close(llr(stats(100, 300, 80)), 0.638);
+        assert!(llr(stats(30, 40, 30)) < 0.0);
+        assert!(llr(stats(200, 200, 600)) < lower);
+        assert!(llr(stats(600, 200, 200)) > upper);
+        // No variance, nothing to decide on
+        assert_eq!(llr(stats(0, 10, 0)), 0.0);
+    }
+
+    #[test]
+    fn parsing() {
+        let tc = TimeControl::parse("10+0.1").unwrap();
+        assert_eq!((tc.base, tc.inc), (10000, 100));
+        let tc = TimeControl::parse("60").unwrap();
+        assert_eq!((tc.base, tc.inc), (60000, 0));
+        assert!(TimeControl::parse("x+1").is_none());
+
+        let info = "info depth 12 seldepth 20 score cp -35 nodes 1000 pv e2e4";
+        assert_eq!(parse_score(info), Some(-35));
+        let info = "info depth 20 score mate -3 pv e2e4";
+        assert_eq!(parse_score(info), Some(-(MATE as i32)));
+        assert_eq!(parse_score("info depth 20 score mate 1"), Some(MATE as i32));
+        assert_eq!(parse_score("info depth 1 nodes 20"), None);
+    }
+}
@@ -27,6 +27,12 @@ pub mod hash {
     pub const CTM: u64 = 13442441245975073873;
     #[rustfmt::skip]
     pub const CASTLE_RIGHTS: [u64; 16] = [0, 2813347996350729101, 4323917982890274150, 17639880714067218301, 6192459193442042619, 10431259969583463413, 6842244024206355455, 16007120865564466847, 1121671930296507050, 15415905829452006450, 9786278101810930012, 4806855920115420048, 11019280650726627950, 17847013704274833886, 5689581433056223789, 18324940204714547484];
+    // Three-check counters, no checks given hashes to 0 so other variants are unaffected
+    #[rustfmt::skip]
+    pub const CHECKS: [[u64; 4]; 2] = [
+        [0, 6351765325467307773, 17216752865969326055, 5967433424167373258],
+        [0, 10226050383177474163, 14900179787625764029, 12541228737015279450],
+    ];
     #[rustfmt::skip]
     pub const EP: [u64; 64] = [
         0, 0, 0, 0, 0, 0, 0, 0,
@@ -55,11 +61,11 @@ pub const AGE_MASK: u8 = !FLAGS;
 #[repr(C, align(16))]
 pub struct TTEntry {
     pub hash: u64,      //8 byte
-    pub mv: Move,       //2 byte
+    pub mv: Move,       //4 byte
     score: Score,       //2 byte
     pub depth: u8,      //1 byte
     pub age_bound: u8, //1 byte
-                  // Sum: 14 byte
+                  // Sum: 16 byte
              //Allocated: 16 byte
              //-> Relying on the fact that writes are atomic
              // such that we can assume the mv: Move corresponds
@@ -123,10 +129,15 @@ impl TT {
         let mut entries: usize = size_in_mb * 1024 * 1024 / 16;
         assert_ne!(entries, 0);
         entries = 1 << (BITS_USIZE - 1 - entries.leading_zeros()); //Round down to nearest integer of power 2
-        self.entries = vec![TTEntry::default(); entries as usize];
+        self.entries = vec![TTEntry::default(); entries];
         self.index_mask = entries - 1;
     }
 
+    pub fn clear(&mut self) {
+        self.entries.fill(TTEntry::default());
+        self.age = 0;
+    }
+
     pub fn read(&mut self, pos: &Position) -> Option<&TTEntry> {
         let entry = &mut self.entries[pos.hash as usize & self.index_mask];
         if entry.is_hit(pos) {
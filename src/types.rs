@@ -10,10 +10,18 @@ pub const fn mate_score(height: u8) -> Score {
     -MATE + height as Score
 }
 
+pub const TB_WIN: Score = MATE_IN_MAX - 1000;
+pub const TB_WIN_IN_MAX: Score = TB_WIN - 999;
+
+pub const fn tb_win_score(height: u8) -> Score {
+    TB_WIN - height as Score
+}
+
+// Mate and tablebase win scores depend on the height, the TT stores them relative to the node
 pub const fn score_to_tt(score: Score, height: u8) -> Score {
-    if score > MATE_IN_MAX {
+    if score > TB_WIN_IN_MAX {
         score + height as Score
-    } else if score < -MATE_IN_MAX {
+    } else if score < -TB_WIN_IN_MAX {
         score - height as Score
     } else {
         score
@@ -21,9 +29,9 @@ pub const fn score_to_tt(score: Score, height: u8) -> Score {
 }
 
 pub const fn score_from_tt(score: Score, height: u8) -> Score {
-    if score > MATE_IN_MAX {
+    if score > TB_WIN_IN_MAX {
         score - height as Score
-    } else if score < -MATE_IN_MAX {
+    } else if score < -TB_WIN_IN_MAX {
         score + height as Score
     } else {
         score
@@ -338,6 +346,15 @@ pub const fn distance(sq1: Square, sq2: Square) -> u8 {
     DISTANCE[sq1 as usize][sq2 as usize]
 }
 
+pub const DARK_SQUARES: BitBoard = BitBoard(0xAA55_AA55_AA55_AA55);
+
+pub const fn edge_distance(sq: Square) -> u8 {
+    let (rank, file) = (rank_of(sq), file_of(sq));
+    let rank_dist = [rank, RANK_8 - rank][(rank > RANK_8 - rank) as usize];
+    let file_dist = [file, FILE_H - file][(file > FILE_H - file) as usize];
+    [rank_dist, file_dist][(file_dist < rank_dist) as usize] as u8
+}
+
 // CastleRights
 pub type CastleRights = u8;
 
@@ -0,0 +1,135 @@
+use crate::bitboard::*;
+use crate::position::Position;
+use crate::types::*;
+
+use std::ops::RangeInclusive;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Variant {
+    #[default]
+    Standard,
+    KingOfTheHill,
+    ThreeCheck,
+    Horde,
+    Antichess,
+}
+
+pub const VARIANTS: [Variant; 5] = [
+    Variant::Standard,
+    Variant::KingOfTheHill,
+    Variant::ThreeCheck,
+    Variant::Horde,
+    Variant::Antichess,
+];
+
+pub const CENTER_BB: BitBoard = bb!(D4, E4, D5, E5);
+pub const CHECKS_TO_WIN: u8 = 3;
+
+// Bonuses in the 2**17 scaled evaluation units, 512 per centipawn
+const HILL_DISTANCE_BONUS: [i32; 4] = [0, 60 * 512, 20 * 512, 5 * 512];
+const CHECKS_GIVEN_BONUS: [i32; 4] = [0, 80 * 512, 250 * 512, 0];
+// In antichess every piece is a liability, the ones which are hard to give away the most
+const ANTICHESS_PIECE_PENALTY: [i32; 7] = [
+    0,
+    40 * 512,
+    110 * 512,
+    80 * 512,
+    90 * 512,
+    60 * 512,
+    120 * 512,
+];
+
+impl Variant {
+    // Names as used by UCI_Variant
+    pub fn name(self) -> &'static str {
+        match self {
+            Variant::Standard => "chess",
+            Variant::KingOfTheHill => "kingofthehill",
+            Variant::ThreeCheck => "3check",
+            Variant::Horde => "horde",
+            Variant::Antichess => "antichess",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Variant> {
+        VARIANTS.iter().copied().find(|v| v.name() == name)
+    }
+
+    pub fn start_fen(self) -> &'static str {
+        match self {
+            Variant::ThreeCheck => "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 3+3 0 1",
+            Variant::Horde => {
+                "rnbqkbnr/pppppppp/8/1PP2PP1/PPPPPPPP/PPPPPPPP/PPPPPPPP/PPPPPPPP w kq - 0 1"
+            }
+            Variant::Antichess => "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w - - 0 1",
+            _ => "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+        }
+    }
+
+    // Horde has no white king and antichess kings are ordinary pieces which may be promoted to
+    pub fn king_range(self, c: Color) -> RangeInclusive<u32> {
+        match self {
+            Variant::Horde if c == WHITE => 0..=0,
+            Variant::Antichess => 0..=SQUARE_NB as u32,
+            _ => 1..=1,
+        }
+    }
+
+    pub fn promo_types(self) -> &'static [PieceType] {
+        match self {
+            Variant::Antichess => &[KNIGHT, BISHOP, ROOK, QUEEN, KING],
+            _ => &[KNIGHT, BISHOP, ROOK, QUEEN],
+        }
+    }
+}
+
+impl Position {
+    // The previous move ended the game by a variant rule, which is always a loss for the side to move
+    pub fn is_variant_loss(&self) -> bool {
+        let them = swap_color(self.ctm);
+        match self.variant {
+            Variant::Standard | Variant::Antichess => false,
+            Variant::KingOfTheHill => (self.piece_bb(KING, them) & CENTER_BB).not_empty(),
+            Variant::ThreeCheck => self.checks[them as usize] >= CHECKS_TO_WIN,
+            Variant::Horde => self.color_bb(self.ctm).is_empty(),
+        }
+    }
+
+    // Without legal moves the side to move is mated or stalemated, antichess counts both as a win
+    pub fn no_moves_score(&self, height: u8) -> Score {
+        if self.variant == Variant::Antichess {
+            -mate_score(height)
+        } else if self.in_check(self.ctm) {
+            mate_score(height)
+        } else {
+            0
+        }
+    }
+
+    // A king which can be checked, kings in antichess are ordinary pieces
+    pub fn has_royal_king(&self, c: Color) -> bool {
+        self.variant != Variant::Antichess && self.piece_bb(KING, c).not_empty()
+    }
+}
+
+// Variant specific evaluation from white's point of view, scaled like the PSQT. Antichess
+// replaces the PSQT evaluation entirely.
+pub fn eval(pos: &Position) -> i32 {
+    side_eval(pos, WHITE) - side_eval(pos, BLACK)
+}
+
+// The variant bonus of one side from its own point of view
+pub fn side_eval(pos: &Position, c: Color) -> i32 {
+    match pos.variant {
+        Variant::KingOfTheHill => {
+            let k_sq = pos.king_sq(c);
+            let dist = CENTER_BB.map(|sq| distance(k_sq, sq)).min().unwrap();
+            HILL_DISTANCE_BONUS[dist.min(3) as usize]
+        }
+        Variant::ThreeCheck => CHECKS_GIVEN_BONUS[pos.checks[c as usize] as usize],
+        Variant::Antichess => -(PAWN..=KING)
+            .map(|pt| ANTICHESS_PIECE_PENALTY[pt as usize] * pos.piece_bb(pt, c).popcount() as i32)
+            .sum::<i32>(),
+        _ => 0,
+    }
+}